@@ -0,0 +1,341 @@
+use crate::env::{get_op_color, PietOp};
+use crate::image::PietImg;
+use crate::synth::{Metric, Synth};
+use crate::ty::{Codel, PietColor};
+use std::collections::HashMap;
+
+/// Mnemonics of every command but push, which takes the size of its block as an argument
+const MNEMONICS: [(&str, PietOp); 16] = [
+    ("pop", PietOp::Pop),
+    ("add", PietOp::Add),
+    ("sub", PietOp::Subtract),
+    ("mul", PietOp::Multiply),
+    ("div", PietOp::Divide),
+    ("mod", PietOp::Mod),
+    ("not", PietOp::Not),
+    ("gt", PietOp::Greater),
+    ("pointer", PietOp::Pointer),
+    ("switch", PietOp::Switch),
+    ("dup", PietOp::Duplicate),
+    ("roll", PietOp::Roll),
+    ("innum", PietOp::InNumber),
+    ("inchar", PietOp::InChar),
+    ("outnum", PietOp::OutNumber),
+    ("outchar", PietOp::OutChar),
+];
+
+/// The name a command is written with in assembly
+pub fn mnemonic(op: PietOp) -> &'static str {
+    match op {
+        PietOp::None => "nop",
+        PietOp::Push => "push",
+        _ => MNEMONICS.iter().find(|(_, o)| *o == op).unwrap().0,
+    }
+}
+
+/// Colour of the codels that turn the path through the margins, and of the halting block
+const CORNER: PietColor = PietColor::LightRed;
+/// Colour of a conditional jump's first codel, the second executes a pointer
+const BRANCH: PietColor = PietColor::LightGreen;
+/// Colour every run of commands starts with
+const START: PietColor = PietColor::Red;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    /// Line of the source the error is on, counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    Always,
+    Zero,
+    NotZero,
+}
+
+/// How a run of commands ends, before labels are resolved
+enum Ending {
+    FallThrough,
+    Halt,
+    Jump(Condition, String, usize),
+}
+
+/// A run of commands executed left to right, only ever entered at the start
+#[derive(Default)]
+struct Band {
+    /// Each command with the size of the block it's executed from
+    ops: Vec<(PietOp, u32)>,
+    ending: Option<Ending>,
+}
+
+/// Where a band goes once its commands are done
+enum Exit {
+    Halt,
+    Jump(usize),
+    /// Pops the 1 or 0 the band leaves, going to `taken` on 1 and `otherwise` on 0
+    Branch {
+        taken: usize,
+        otherwise: usize,
+    },
+}
+
+fn parse(source: &str) -> Result<(Vec<Band>, HashMap<String, usize>), AsmError> {
+    let mut bands = vec![];
+    let mut labels = HashMap::new();
+    let mut band = Band::default();
+    let mut labelled = false;
+    let mut synth = Synth::new(Metric::Area);
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let error = |message: String| AsmError {
+            line: line_no,
+            message,
+        };
+        let line = line.split(&['#', ';'][..]).next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(label) = line.strip_suffix(':') {
+            // a label starts a new band unless nothing's been put in this one yet
+            if !band.ops.is_empty() {
+                band.ending = Some(Ending::FallThrough);
+                bands.push(std::mem::take(&mut band));
+            }
+            if labels
+                .insert(label.trim().to_string(), bands.len())
+                .is_some()
+            {
+                return Err(error(format!("{} is already defined", label.trim())));
+            }
+            labelled = true;
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap().to_lowercase();
+        let arg = words.next();
+        let largest = if name == "num" { words.next() } else { None };
+        if words.next().is_some() {
+            return Err(error(format!("too many arguments to {}", name)));
+        }
+        let condition = match name.as_str() {
+            "jmp" => Some(Condition::Always),
+            "jz" => Some(Condition::Zero),
+            "jnz" => Some(Condition::NotZero),
+            _ => None,
+        };
+        match (name.as_str(), arg, condition) {
+            ("push", Some(arg), _) => match arg.parse::<u32>() {
+                Ok(n) if n > 0 => band.ops.push((PietOp::Push, n)),
+                _ => return Err(error(format!("can't push {}, it has to be positive", arg))),
+            },
+            ("num", Some(arg), _) => {
+                let n = match arg.parse::<i64>() {
+                    Ok(n) => n,
+                    _ => return Err(error(format!("{} isn't a number", arg))),
+                };
+                match largest.map(|largest| largest.parse::<u32>()) {
+                    None => band.ops.extend(synth.plan(n)),
+                    Some(Ok(largest)) if largest > 0 => band
+                        .ops
+                        .extend(Synth::new(Metric::Commands(largest)).plan(n)),
+                    Some(_) => return Err(error("blocks pushed have to be positive".to_string())),
+                }
+            }
+            (_, Some(label), Some(condition)) => {
+                band.ending = Some(Ending::Jump(condition, label.to_string(), line_no));
+            }
+            ("halt", None, _) => band.ending = Some(Ending::Halt),
+            (name, None, None) => match MNEMONICS.iter().find(|(m, _)| *m == name) {
+                Some((_, op)) => band.ops.push((*op, 1)),
+                None => return Err(error(format!("unknown command {}", name))),
+            },
+            (name, _, _) => return Err(error(format!("wrong arguments to {}", name))),
+        }
+        if band.ending.is_some() {
+            bands.push(std::mem::take(&mut band));
+            labelled = false;
+        }
+    }
+    let falls_through = bands.last().is_none_or(|last: &Band| {
+        !matches!(
+            last.ending,
+            Some(Ending::Halt) | Some(Ending::Jump(Condition::Always, _, _))
+        )
+    });
+    if !band.ops.is_empty() || labelled || falls_through {
+        band.ending = Some(Ending::Halt);
+        bands.push(band);
+    }
+    Ok((bands, labels))
+}
+
+/// Number of columns a block of `size` codels takes when it can hang `height - 1` codels below
+/// the row it's executed along
+pub fn block_width(size: u32, height: u32) -> u32 {
+    1 + (size - 1).div_ceil(height)
+}
+
+/// Assemble a program into an image that runs in `PietEnv`.
+///
+/// Each line holds one command, a `label:`, or nothing, and anything after `;` or `#` is a
+/// comment. `push N` pushes a positive number with a block of that size, `num N` pushes any
+/// number with whatever commands take the fewest codels, `num N S` with the fewest commands
+/// pushing no block bigger than `S`, `jmp label` always jumps, `jz label` and `jnz label` pop
+/// a value and jump if it's zero or not, and `halt` ends the program, as does running off the
+/// end of it. The rest of the commands are `pop`, `add`, `sub`, `mul`, `div`,
+/// `mod`, `not`, `gt`, `pointer`, `switch`, `dup`, `roll`, `innum`, `inchar`, `outnum` and
+/// `outchar`; `pointer` turns off the laid out path unless it pops a multiple of four.
+///
+/// Commands between labels and jumps become rows of blocks run left to right. Every jump, and
+/// falling through to the next label, goes through its own lane around the margins: right
+/// and down to a row below the program, left, then up into the row it's going to. Lanes are
+/// white so following them never executes anything, and turn on single codels walled off with
+/// black.
+pub fn assemble(source: &str) -> Result<PietImg, AsmError> {
+    let (bands, labels) = parse(source)?;
+
+    let mut exits = vec![];
+    for (i, band) in bands.iter().enumerate() {
+        exits.push(match band.ending.as_ref().unwrap() {
+            Ending::Halt => Exit::Halt,
+            Ending::FallThrough => Exit::Jump(i + 1),
+            Ending::Jump(condition, label, line) => {
+                let target = *labels.get(label).ok_or_else(|| AsmError {
+                    line: *line,
+                    message: format!("no label called {}", label),
+                })?;
+                match condition {
+                    Condition::Always => Exit::Jump(target),
+                    _ => Exit::Branch {
+                        taken: target,
+                        otherwise: i + 1,
+                    },
+                }
+            }
+        });
+    }
+
+    // A branch turns its value into 1 to take the jump and 0 not to, then uses it with a pointer
+    let bands: Vec<Vec<(PietOp, u32)>> = bands
+        .into_iter()
+        .map(|band| {
+            let mut ops = band.ops;
+            match band.ending {
+                Some(Ending::Jump(Condition::Zero, _, _)) => ops.push((PietOp::Not, 1)),
+                Some(Ending::Jump(Condition::NotZero, _, _)) => {
+                    ops.extend([(PietOp::Not, 1), (PietOp::Not, 1)])
+                }
+                _ => {}
+            }
+            ops
+        })
+        .collect();
+
+    // Rows of codels each band can hang blocks below the one it runs along
+    let largest = bands.iter().flatten().map(|(_, size)| *size).max();
+    let height = 2.max((largest.unwrap_or(1) as f64).sqrt().ceil() as u32);
+    let band_y = |band: usize| 3 + band as u32 * (height + 2);
+
+    // Each lane starts on a row going right and ends going into a band, the first leaves
+    // the start of the program for the first band
+    let mut lanes = vec![(0, 0, false)];
+    for (i, exit) in exits.iter().enumerate() {
+        match *exit {
+            Exit::Halt => {}
+            Exit::Jump(target) => lanes.push((band_y(i), target, false)),
+            Exit::Branch { taken, otherwise } => {
+                lanes.push((band_y(i), taken, true));
+                lanes.push((band_y(i), otherwise, false));
+            }
+        }
+    }
+    let mut targets: Vec<usize> = vec![];
+    for (_, target, _) in &lanes {
+        if !targets.contains(target) {
+            targets.push(*target);
+        }
+    }
+
+    let code_width = bands
+        .iter()
+        .map(|ops| {
+            let widths: u32 = ops.iter().map(|(_, size)| block_width(*size, height)).sum();
+            widths + 1
+        })
+        .max()
+        .unwrap();
+    let x0 = 2 * targets.len() as u32 + 1;
+    let x1 = x0 + code_width + 3;
+    let bottom = band_y(bands.len()) - 1;
+    let width = x1 + 2 * lanes.len() as u32 + 1;
+    let mut image = PietImg::filled(width, bottom + 2 * lanes.len() as u32, PietColor::White);
+
+    for y in 1..bottom {
+        for x in x0..x1 {
+            image.set(Codel::new(x, y), PietColor::Black);
+        }
+    }
+    image.set(Codel::new(0, 0), CORNER);
+
+    for (i, ops) in bands.iter().enumerate() {
+        let y = band_y(i);
+        let mut x = x0;
+        let mut color = START;
+        for (op, size) in ops.iter().copied().chain([(PietOp::None, 1)]) {
+            let block_width = block_width(size, height);
+            for column in 0..block_width {
+                image.set(Codel::new(x + column, y), color);
+            }
+            for codel in 0..size - block_width {
+                let (column, row) = (codel / (height - 1), codel % (height - 1));
+                image.set(Codel::new(x + column, y + 1 + row), color);
+            }
+            x += block_width;
+            if op != PietOp::None {
+                color = get_op_color(color, op);
+            }
+        }
+        for x in x..x1 {
+            image.set(Codel::new(x, y), PietColor::White);
+        }
+        if let Exit::Halt = exits[i] {
+            // a column of three entered in the middle, every way out of it is blocked
+            let halt = x1 - 2;
+            for y in y - 1..=y + 1 {
+                image.set(Codel::new(halt, y), CORNER);
+                image.set(Codel::new(halt + 1, y), PietColor::Black);
+            }
+        }
+    }
+
+    for (k, (y, target, branch)) in lanes.iter().copied().enumerate() {
+        let right = x1 + 1 + 2 * k as u32;
+        let low = bottom + 2 * k as u32;
+        let m = targets.iter().position(|t| *t == target).unwrap() as u32;
+        let left = x0 - 2 - 2 * m;
+        if branch {
+            image.set(Codel::new(right - 1, y), BRANCH);
+            image.set(Codel::new(right, y), get_op_color(BRANCH, PietOp::Pointer));
+        } else {
+            image.set(Codel::new(right, y), CORNER);
+            image.set(Codel::new(right + 1, y), PietColor::Black);
+        }
+        image.set(Codel::new(right, low), CORNER);
+        image.set(Codel::new(right, low + 1), PietColor::Black);
+        image.set(Codel::new(left, low), CORNER);
+        image.set(Codel::new(left - 1, low), PietColor::Black);
+        image.set(Codel::new(left, band_y(target)), CORNER);
+        image.set(Codel::new(left, band_y(target) - 1), PietColor::Black);
+    }
+
+    Ok(image)
+}
@@ -0,0 +1,263 @@
+use crate::asm::mnemonic;
+use crate::dialect::Dialect;
+use crate::disasm::{chooser, direction, Block, Disassembly, Outcome};
+use crate::env::PietOp;
+use crate::ty::{CodelChoser, DirectionPointer};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// A block being left with a particular DP and CC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct State {
+    /// Index of the block in the disassembly
+    pub block: usize,
+    pub dp: DirectionPointer,
+    pub cc: CodelChoser,
+}
+
+impl State {
+    pub fn new(block: usize, dp: DirectionPointer, cc: CodelChoser) -> Self {
+        State { block, dp, cc }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// The command executed, `None` when the way out is blocked
+    pub command: Option<PietOp>,
+    /// For a pointer or switch, the value modulo 4 or 2 that takes this edge
+    pub branch: Option<i64>,
+}
+
+/// Every (block, DP, CC) state a program can reach and the moves between them
+pub struct Cfg<'a> {
+    disassembly: &'a Disassembly,
+    pub states: Vec<State>,
+    pub edges: Vec<Edge>,
+    /// States that slide into white and never get out
+    pub trapped: Vec<usize>,
+}
+
+impl<'a> Cfg<'a> {
+    /// Pointers and switches get an edge for every way they can turn, blocked states one for
+    /// toggling the CC and one for turning the DP, as either could be next.
+    pub fn new(disassembly: &'a Disassembly) -> Self {
+        let mut states = vec![];
+        for (block, exits) in disassembly.blocks.iter().enumerate() {
+            for (dp, cc, _) in &exits.exits {
+                states.push(State::new(block, *dp, *cc));
+            }
+        }
+        let ids: HashMap<State, usize> = states.iter().enumerate().map(|(i, s)| (*s, i)).collect();
+
+        let mut edges = vec![];
+        let mut trapped = vec![];
+        let mut edge = |from: usize, to: State, command, branch| {
+            edges.push(Edge {
+                from,
+                to: ids[&to],
+                command,
+                branch,
+            })
+        };
+        let outcomes = disassembly
+            .blocks
+            .iter()
+            .flat_map(|block| block.exits.iter().map(|(_, _, outcome)| outcome));
+        for ((from, state), outcome) in states.iter().enumerate().zip(outcomes) {
+            match *outcome {
+                Outcome::Move {
+                    op: PietOp::Pointer,
+                    target,
+                    mut dp,
+                    cc,
+                } => {
+                    for n in 0..4 {
+                        edge(
+                            from,
+                            State::new(target, dp, cc),
+                            Some(PietOp::Pointer),
+                            Some(n),
+                        );
+                        dp = dp.clockwise();
+                    }
+                }
+                Outcome::Move {
+                    op: PietOp::Switch,
+                    target,
+                    dp,
+                    mut cc,
+                } => {
+                    for n in 0..2 {
+                        edge(
+                            from,
+                            State::new(target, dp, cc),
+                            Some(PietOp::Switch),
+                            Some(n),
+                        );
+                        cc = cc.toggled();
+                    }
+                }
+                Outcome::Move { op, target, dp, cc } => {
+                    edge(from, State::new(target, dp, cc), Some(op), None)
+                }
+                Outcome::Blocked => {
+                    let State { block, dp, cc } = *state;
+                    edge(from, State::new(block, dp, cc.toggled()), None, None);
+                    edge(from, State::new(block, dp.clockwise(), cc), None, None);
+                }
+                Outcome::Trapped => trapped.push(from),
+            }
+        }
+        Cfg {
+            disassembly,
+            states,
+            edges,
+            trapped,
+        }
+    }
+
+    /// What the program was disassembled as
+    pub fn dialect(&self) -> &Dialect {
+        &self.disassembly.dialect
+    }
+
+    /// The block a state leaves
+    pub fn block(&self, state: usize) -> &Block {
+        &self.disassembly.blocks[self.states[state].block]
+    }
+
+    /// The edges out of each state, in the order `new` gives them
+    pub fn edges_from(&self) -> Vec<Vec<&Edge>> {
+        let mut from = vec![vec![]; self.states.len()];
+        for edge in &self.edges {
+            from[edge.from].push(edge);
+        }
+        from
+    }
+
+    pub fn label(&self, edge: &Edge) -> String {
+        let block = self.block(edge.from);
+        match (edge.command, edge.branch) {
+            (None, _) => "blocked".to_string(),
+            (Some(PietOp::Push), _) => format!("push {}", block.size),
+            (Some(PietOp::None), _) => "slide".to_string(),
+            (Some(op), Some(n)) => format!("{} {}", mnemonic(op), n),
+            (Some(op), None) => mnemonic(op).to_string(),
+        }
+    }
+
+    /// The graph for Graphviz, with the states of each block grouped together
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph program {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=monospace];").unwrap();
+        for (i, block) in self.disassembly.blocks.iter().enumerate() {
+            let [r, g, b] = block.color.rgb();
+            writeln!(out, "    subgraph cluster_{} {{", i).unwrap();
+            writeln!(
+                out,
+                "        label=\"block {} at ({}, {})\"; style=filled; fillcolor=\"#{:02x}{:02x}{:02x}\";",
+                i, block.codel.x, block.codel.y, r, g, b
+            )
+            .unwrap();
+            for (id, state) in self.states.iter().enumerate() {
+                if state.block != i {
+                    continue;
+                }
+                let shape = if self.trapped.contains(&id) {
+                    ", peripheries=2"
+                } else {
+                    ""
+                };
+                writeln!(
+                    out,
+                    "        s{} [label=\"{} {}\", style=filled, fillcolor=white{}];",
+                    id,
+                    direction(state.dp),
+                    chooser(state.cc),
+                    shape
+                )
+                .unwrap();
+            }
+            writeln!(out, "    }}").unwrap();
+        }
+        for edge in &self.edges {
+            let style = match edge.command {
+                None => ", style=dashed",
+                Some(PietOp::Pointer) | Some(PietOp::Switch) => ", color=blue",
+                _ => "",
+            };
+            writeln!(
+                out,
+                "    s{} -> s{} [label=\"{}\"{}];",
+                edge.from,
+                edge.to,
+                self.label(edge),
+                style
+            )
+            .unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    /// The graph as JSON, with blocks, states and edges referring to each other by index
+    pub fn to_json(&self) -> String {
+        let blocks: Vec<String> = self
+            .disassembly
+            .blocks
+            .iter()
+            .map(|block| {
+                format!(
+                    "{{\"x\": {}, \"y\": {}, \"color\": \"{:?}\", \"size\": {}}}",
+                    block.codel.x, block.codel.y, block.color, block.size
+                )
+            })
+            .collect();
+        let states: Vec<String> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(id, state)| {
+                format!(
+                    "{{\"block\": {}, \"dp\": \"{}\", \"cc\": \"{}\", \"trapped\": {}}}",
+                    state.block,
+                    direction(state.dp),
+                    chooser(state.cc),
+                    self.trapped.contains(&id)
+                )
+            })
+            .collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                let command = match edge.command {
+                    Some(op) => format!("\"{}\"", mnemonic(op)),
+                    None => "null".to_string(),
+                };
+                let branch = match edge.branch {
+                    Some(n) => n.to_string(),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "{{\"from\": {}, \"to\": {}, \"command\": {}, \"branch\": {}, \"label\": \"{}\"}}",
+                    edge.from,
+                    edge.to,
+                    command,
+                    branch,
+                    self.label(edge)
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"blocks\": [\n    {}\n  ],\n  \"states\": [\n    {}\n  ],\n  \"edges\": [\n    {}\n  ]\n}}\n",
+            blocks.join(",\n    "),
+            states.join(",\n    "),
+            edges.join(",\n    ")
+        )
+    }
+}
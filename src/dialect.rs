@@ -0,0 +1,122 @@
+/// How a program runs where interpreters disagree, or the spec doesn't say
+#[derive(Debug, Clone, Copy, PartialEq, druid::Data)]
+pub struct Dialect {
+    pub white: White,
+    pub negative_roll: NegativeRoll,
+    /// Which value `mod` takes the sign of
+    pub modulo: Sign,
+    pub division: Rounding,
+    /// What `inchar` and `innum` do when there's nothing left to read
+    pub eof: Eof,
+    /// What `outchar` does with a value that isn't a character
+    pub invalid_char: InvalidChar,
+}
+
+/// How white codels are moved into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data)]
+pub enum White {
+    /// Slide straight across to the colour block on the other side, toggling the CC and turning
+    /// the DP on the way when blocked, and halting if trapped
+    Slide,
+    /// Stop in white as if it were a colour block, with no command going into or out of it
+    Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data)]
+pub enum NegativeRoll {
+    /// Leave the stack as it is and carry on
+    Ignore,
+    /// Stop the program with an error
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data)]
+pub enum Sign {
+    Divisor,
+    Dividend,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data)]
+pub enum Rounding {
+    Down,
+    TowardZero,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data)]
+pub enum Eof {
+    /// Wait for more input to be given
+    Wait,
+    /// Carry on without pushing anything
+    Ignore,
+    /// Carry on, pushing the value
+    Push(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, druid::Data)]
+pub enum InvalidChar {
+    /// Stop the program with an error, leaving the value on the stack
+    Error,
+    /// Pop the value and print nothing
+    Ignore,
+    /// Pop the value and print U+FFFD
+    Replace,
+}
+
+impl Dialect {
+    /// What the spec says, with division rounded down to agree with `mod` and waiting on input
+    /// as the spec doesn't say either way
+    pub const SPEC: Dialect = Dialect {
+        white: White::Slide,
+        negative_roll: NegativeRoll::Ignore,
+        modulo: Sign::Divisor,
+        division: Rounding::Down,
+        eof: Eof::Wait,
+        invalid_char: InvalidChar::Error,
+    };
+
+    /// npiet, which divides the way C does and takes `getchar`'s -1 at the end of input
+    pub const NPIET: Dialect = Dialect {
+        white: White::Slide,
+        negative_roll: NegativeRoll::Ignore,
+        modulo: Sign::Divisor,
+        division: Rounding::TowardZero,
+        eof: Eof::Push(-1),
+        invalid_char: InvalidChar::Ignore,
+    };
+
+    /// PietDev, which stops in white, takes `mod` the way JavaScript's `%` does and stops on a
+    /// negative roll
+    pub const PIET_DEV: Dialect = Dialect {
+        white: White::Block,
+        negative_roll: NegativeRoll::Error,
+        modulo: Sign::Dividend,
+        division: Rounding::Down,
+        eof: Eof::Ignore,
+        invalid_char: InvalidChar::Replace,
+    };
+
+    /// Each of the above with the name it's known by
+    pub const PRESETS: [(&'static str, Dialect); 3] = [
+        ("Spec", Dialect::SPEC),
+        ("npiet", Dialect::NPIET),
+        ("PietDev", Dialect::PIET_DEV),
+    ];
+
+    /// `a / b`, or `None` if it's ignored
+    pub fn divide(&self, a: i64, b: i64) -> Option<i64> {
+        let (q, r) = (a.checked_div(b)?, a.checked_rem(b)?);
+        Some(match self.division {
+            Rounding::Down if r != 0 && (r < 0) != (b < 0) => q - 1,
+            _ => q,
+        })
+    }
+
+    /// `a mod b`, or `None` if it's ignored
+    pub fn remainder(&self, a: i64, b: i64) -> Option<i64> {
+        let r = a.checked_rem(b)?;
+        Some(match self.modulo {
+            Sign::Divisor if r != 0 && (r < 0) != (b < 0) => r + b,
+            _ => r,
+        })
+    }
+}
@@ -0,0 +1,239 @@
+use crate::asm::mnemonic;
+use crate::dialect::Dialect;
+use crate::env::{PietEnv, PietOp};
+use crate::image::PietImg;
+use crate::ty::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+pub const DIRECTIONS: [DirectionPointer; 4] = [
+    DirectionPointer::Right,
+    DirectionPointer::Down,
+    DirectionPointer::Left,
+    DirectionPointer::Up,
+];
+pub const CHOOSERS: [CodelChoser; 2] = [CodelChoser::Left, CodelChoser::Right];
+
+/// What leaving a block with a particular DP and CC does
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Executes `op` going into `target`, with the DP and CC it's entered with. Sliding across
+    /// white executes nothing and can turn them.
+    Move {
+        op: PietOp,
+        target: usize,
+        dp: DirectionPointer,
+        cc: CodelChoser,
+    },
+    /// The way out is black or the edge of the program
+    Blocked,
+    /// Slides into white that never leads anywhere, ending the program
+    Trapped,
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    /// The first codel of the block, row by row
+    pub codel: Codel,
+    pub color: PietColor,
+    pub size: usize,
+    /// Every DP and CC the block can be left with, in the order they were found
+    pub exits: Vec<(DirectionPointer, CodelChoser, Outcome)>,
+}
+
+/// Every block reachable from the start of a program and the ways out of each
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    /// Blocks in the order they were reached, the first is where the program starts
+    pub blocks: Vec<Block>,
+    /// What the program was walked as, which decides whether white is a block of its own
+    pub dialect: Dialect,
+}
+
+/// What leaving the block at `codel` does, worked out the same way a step is
+fn outcome(env: &mut PietEnv, codel: Codel, dp: DirectionPointer, cc: CodelChoser) -> Outcome {
+    env.cp = codel;
+    env.dp = dp;
+    env.cc = cc;
+    let transition = env.next_transition();
+    let (entry, op) = match (transition.entry, transition.op) {
+        (Some(entry), Some(op)) => (entry, op),
+        _ => {
+            let white = transition
+                .exit
+                .block_in_dir(dp)
+                .filter(|next| env.image.contains(*next))
+                .filter(|next| env.image.color(*next) == PietColor::White);
+            return match white {
+                Some(_) => Outcome::Trapped,
+                None => Outcome::Blocked,
+            };
+        }
+    };
+    let (dp, cc) = match transition.exit.block_in_dir(dp) {
+        Some(next) if next != entry => {
+            let (_, dp, cc) = env.slide(next).unwrap();
+            (dp, cc)
+        }
+        _ => (dp, cc),
+    };
+    Outcome::Move {
+        op,
+        target: env.image.block_id(entry),
+        dp,
+        cc,
+    }
+}
+
+/// Walk a program from its first codel through every DP and CC it could be in, without running
+/// any commands. A blocked exit leads to the block being left with the CC toggled or the DP
+/// turned, and a pointer or switch to every DP or CC, so this finds everything that could run
+/// and possibly more. White is slid across or stopped in as `dialect` says.
+pub fn disassemble(image: &PietImg, dialect: &Dialect) -> Disassembly {
+    let mut env = PietEnv::new(image.clone(), *dialect);
+    let mut blocks: Vec<Block> = vec![];
+    // image block id to index in `blocks`
+    let mut found: HashMap<usize, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    let start = Codel::new(0, 0);
+    if matches!(image.color(start), PietColor::Black | PietColor::White) {
+        return Disassembly {
+            blocks,
+            dialect: *dialect,
+        };
+    }
+    queue.push_back((
+        image.block_id(start),
+        DirectionPointer::Right,
+        CodelChoser::Left,
+    ));
+
+    while let Some((id, dp, cc)) = queue.pop_front() {
+        let index = *found.entry(id).or_insert_with(|| {
+            let block = &image.blocks().blocks[id];
+            let codel = block.codels[0];
+            blocks.push(Block {
+                codel,
+                color: image.color(codel),
+                size: block.codels.len(),
+                exits: vec![],
+            });
+            blocks.len() - 1
+        });
+        if blocks[index]
+            .exits
+            .iter()
+            .any(|(d, c, _)| (*d, *c) == (dp, cc))
+        {
+            continue;
+        }
+        let outcome = outcome(&mut env, blocks[index].codel, dp, cc);
+        match outcome {
+            Outcome::Move { op, target, dp, cc } => {
+                let dps: &[DirectionPointer] = match op {
+                    PietOp::Pointer => &DIRECTIONS,
+                    _ => &[dp],
+                };
+                let ccs: &[CodelChoser] = match op {
+                    PietOp::Switch => &CHOOSERS,
+                    _ => &[cc],
+                };
+                for dp in dps {
+                    for cc in ccs {
+                        queue.push_back((target, *dp, *cc));
+                    }
+                }
+            }
+            Outcome::Blocked => {
+                queue.push_back((id, dp, cc.toggled()));
+                queue.push_back((id, dp.clockwise(), cc));
+            }
+            Outcome::Trapped => {}
+        }
+        blocks[index].exits.push((dp, cc, outcome));
+    }
+
+    // refer to targets by their index in the listing
+    for block in &mut blocks {
+        for (_, _, outcome) in &mut block.exits {
+            if let Outcome::Move { target, .. } = outcome {
+                *target = found[target];
+            }
+        }
+    }
+    Disassembly {
+        blocks,
+        dialect: *dialect,
+    }
+}
+
+pub fn direction(dp: DirectionPointer) -> &'static str {
+    match dp {
+        DirectionPointer::Right => "right",
+        DirectionPointer::Down => "down",
+        DirectionPointer::Left => "left",
+        DirectionPointer::Up => "up",
+    }
+}
+
+pub fn chooser(cc: CodelChoser) -> &'static str {
+    match cc {
+        CodelChoser::Left => "left",
+        CodelChoser::Right => "right",
+    }
+}
+
+impl std::fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(
+                f,
+                "block {} at ({}, {}): {:?}, {} codel{}",
+                i,
+                block.codel.x,
+                block.codel.y,
+                block.color,
+                block.size,
+                if block.size == 1 { "" } else { "s" }
+            )?;
+            let mut exits = block.exits.clone();
+            exits.sort_by_key(|(dp, cc, _)| {
+                (
+                    DIRECTIONS.iter().position(|d| d == dp),
+                    CHOOSERS.iter().position(|c| c == cc),
+                )
+            });
+            for (dp, cc, outcome) in exits {
+                let mut line = format!("    dp {} cc {}: ", direction(dp), chooser(cc));
+                match outcome {
+                    Outcome::Move {
+                        op,
+                        target,
+                        dp: to_dp,
+                        cc: to_cc,
+                    } => {
+                        match op {
+                            PietOp::Push => write!(line, "push {}", block.size)?,
+                            PietOp::None => line.push_str("slide"),
+                            _ => write!(line, "{}", mnemonic(op))?,
+                        }
+                        let to = &self.blocks[target];
+                        write!(
+                            line,
+                            " -> block {} at ({}, {})",
+                            target, to.codel.x, to.codel.y
+                        )?;
+                        if (to_dp, to_cc) != (dp, cc) {
+                            write!(line, ", now dp {} cc {}", direction(to_dp), chooser(to_cc))?;
+                        }
+                    }
+                    Outcome::Blocked => line.push_str("blocked"),
+                    Outcome::Trapped => line.push_str("trapped in white, halts"),
+                }
+                writeln!(f, "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use crate::asm::assemble;
+use crate::cfg::Cfg;
+use crate::disasm::disassemble;
+use crate::env::{get_op_color, PietOp, OPS};
+use crate::generate::print_program;
+use crate::history::Edit;
+use crate::image::PietImg;
+use crate::layout::Layout;
+use crate::lint::{lint_pixels, lint_png, to_json, to_text};
+use crate::transpile::to_rust;
+use crate::ty::{Codel, PietColor};
+use crate::wat::to_wat;
+use crate::AppData;
+use druid::commands::{OPEN_FILE, SAVE_FILE_AS, SHOW_OPEN_PANEL, SHOW_SAVE_PANEL};
+use druid::piet::{FontFamily, Text, TextLayout, TextLayoutBuilder};
+use druid::widget::{Button, Controller, Flex, Label, Painter, RadioGroup, Stepper, TextBox};
+use druid::{
+    Color, Data, Env, Event, EventCtx, FileDialogOptions, FileSpec, RenderContext, Widget,
+    WidgetExt,
+};
+
+/// What a click in the program view does while editing
+#[derive(Clone, Copy, Data, Debug, PartialEq)]
+pub enum Tool {
+    /// Paint single codels, dragging paints a stroke
+    Pencil,
+    /// Recolour the whole block under the mouse
+    Fill,
+}
+
+const SWATCH: f64 = 22.0;
+const COMMAND_WIDTH: f64 = 72.0;
+const PNG: FileSpec = FileSpec::new("PNG image", &["png"]);
+const ASSEMBLY: FileSpec = FileSpec::new("Piet assembly", &["pasm", "txt"]);
+const LISTING: FileSpec = FileSpec::new("Disassembly", &["lst"]);
+const DOT: FileSpec = FileSpec::new("Control flow graph (Graphviz)", &["dot", "gv"]);
+const JSON: FileSpec = FileSpec::new("Control flow graph (JSON)", &["json"]);
+const RUST: FileSpec = FileSpec::new("Rust source", &["rs"]);
+const WAT: FileSpec = FileSpec::new("WebAssembly text", &["wat"]);
+const LINT: FileSpec = FileSpec::new("Lint report", &["txt"]);
+const LINT_JSON: FileSpec = FileSpec::new("Lint report (JSON)", &["lint.json"]);
+
+impl AppData {
+    /// Swap in an edited program, re-running it up to where execution had got to
+    pub fn replace_image(&mut self, image: PietImg) {
+        self.running = false;
+        // what was hovered over may not be there any more
+        self.hover = None;
+        self.canvas_width = image.png_info.width as f64;
+        self.canvas_height = image.png_info.height as f64;
+        self.env.rerun(image);
+    }
+
+    /// Start a brush stroke. Its codels are painted straight onto the program and undone
+    /// together, and the program's only re-run once the stroke's over.
+    pub fn begin_stroke(&mut self) {
+        self.running = false;
+        self.history.begin_group();
+    }
+
+    pub fn end_stroke(&mut self) {
+        if self.history.in_group() {
+            self.history.end_group();
+            self.replace_image(self.env.image.clone());
+        }
+    }
+
+    /// Use the current tool with the brush colour on a codel
+    pub fn apply_tool(&mut self, codel: Codel) {
+        let before = self.env.image.color(codel);
+        if before == self.brush {
+            return;
+        }
+        let codels = match self.tool {
+            Tool::Pencil => vec![codel],
+            Tool::Fill => self.env.image.block(codel).codels.clone(),
+        };
+        self.edit(Edit::Paint(
+            codels
+                .into_iter()
+                .map(|codel| (codel, before, self.brush))
+                .collect(),
+        ));
+    }
+
+    /// Replace the program with one that prints `print_text`, laid out with `layout`
+    pub fn generate_print(&mut self) {
+        let before = self.env.image.clone();
+        let after = print_program(&self.print_text, self.layout);
+        self.edit(Edit::Replace { before, after });
+    }
+
+    pub fn resize_canvas(&mut self) {
+        let before = self.env.image.clone();
+        let after = before.resized(self.canvas_width as u32, self.canvas_height as u32);
+        self.edit(Edit::Replace { before, after });
+    }
+
+    /// Make a change to the program that can be undone
+    fn edit(&mut self, edit: Edit) {
+        if self.history.in_group() {
+            edit.apply(&mut self.env.image);
+            self.history.record(edit);
+            return;
+        }
+        let mut image = self.env.image.clone();
+        edit.apply(&mut image);
+        self.history.record(edit);
+        self.replace_image(image);
+    }
+}
+
+fn swatch(color: PietColor) -> impl Widget<AppData> {
+    let [r, g, b] = color.rgb();
+    Painter::new(move |ctx, data: &AppData, _env| {
+        let rect = ctx.size().to_rect();
+        ctx.fill(rect, &Color::rgb8(r, g, b));
+        if data.brush == color {
+            ctx.stroke(rect.inset(-1.5), &Color::grey8(0x80), 3.0);
+        }
+    })
+    .fix_size(SWATCH, SWATCH)
+    .on_click(move |_ctx, data: &mut AppData, _env| data.brush = color)
+}
+
+/// The 18 colours of the hue/lightness cycle in a grid, plus black and white
+fn palette() -> impl Widget<AppData> {
+    let mut palette = Flex::column();
+    for lightness in 0..3 {
+        let mut row = Flex::row();
+        for hue in 0..6 {
+            row.add_child(swatch(PietColor::from_color_scale(hue, lightness)));
+        }
+        palette.add_child(row);
+    }
+    palette.with_child(
+        Flex::row()
+            .with_child(swatch(PietColor::White))
+            .with_child(swatch(PietColor::Black)),
+    )
+}
+
+/// The colour that executes `op` when moved into from a block of the brush colour, if the brush
+/// has a hue at all
+fn command_color(brush: PietColor, op: PietOp) -> Option<PietColor> {
+    match brush {
+        PietColor::Black | PietColor::White => None,
+        _ => Some(get_op_color(brush, op)),
+    }
+}
+
+fn command(op: PietOp) -> impl Widget<AppData> {
+    Painter::new(move |ctx, data: &AppData, _env| {
+        let rect = ctx.size().to_rect();
+        let color = command_color(data.brush, op);
+        let (background, text) = match color.map(|color| (color, color.get_color_scale())) {
+            Some((color, (_, lightness))) => {
+                let [r, g, b] = color.rgb();
+                let text = if lightness == 2 {
+                    Color::WHITE
+                } else {
+                    Color::BLACK
+                };
+                (Color::rgb8(r, g, b), text)
+            }
+            None => (Color::grey8(0x40), Color::grey8(0x80)),
+        };
+        ctx.fill(rect, &background);
+        let layout = ctx
+            .text()
+            .new_text_layout(format!("{:?}", op))
+            .font(FontFamily::SYSTEM_UI, 11.0)
+            .text_color(text)
+            .build()
+            .unwrap();
+        let origin = rect.center() - layout.size().to_vec2() / 2.0;
+        ctx.draw_text(&layout, origin);
+    })
+    .fix_size(COMMAND_WIDTH, SWATCH)
+    .on_click(move |_ctx, data: &mut AppData, _env| {
+        if let Some(color) = command_color(data.brush, op) {
+            data.brush = color;
+        }
+    })
+}
+
+/// Every command laid out like the table in the spec, picking one sets the brush to the colour
+/// that executes it when moved into from a block of the current brush colour
+fn command_palette() -> impl Widget<AppData> {
+    let mut palette = Flex::column();
+    for row in OPS.iter() {
+        let mut commands = Flex::row();
+        for op in row {
+            commands.add_child(command(*op).padding(1.0));
+        }
+        palette.add_child(commands);
+    }
+    palette
+}
+
+/// Palette, tools, canvas size, generating and saving, shown while editing
+pub fn build_editor() -> impl Widget<AppData> {
+    let size = Flex::column()
+        .with_child(
+            Flex::row()
+                .with_child(Label::dynamic(|data: &AppData, _| {
+                    format!("Width {}", data.canvas_width)
+                }))
+                .with_child(
+                    Stepper::new()
+                        .with_range(1.0, 4096.0)
+                        .with_step(1.0)
+                        .lens(AppData::canvas_width),
+                ),
+        )
+        .with_child(
+            Flex::row()
+                .with_child(Label::dynamic(|data: &AppData, _| {
+                    format!("Height {}", data.canvas_height)
+                }))
+                .with_child(
+                    Stepper::new()
+                        .with_range(1.0, 4096.0)
+                        .with_step(1.0)
+                        .lens(AppData::canvas_height),
+                ),
+        )
+        .with_child(
+            Button::new("Resize").on_click(|_ctx, data: &mut AppData, _env| data.resize_canvas()),
+        );
+
+    let generate = Flex::column()
+        .with_child(
+            TextBox::new()
+                .with_placeholder("Text to print")
+                .lens(AppData::print_text),
+        )
+        .with_child(
+            RadioGroup::new(vec![
+                ("Row", Layout::Row),
+                ("Snake", Layout::Snake),
+                ("Spiral", Layout::Spiral),
+            ])
+            .lens(AppData::layout),
+        )
+        .with_child(
+            Button::new("Generate")
+                .on_click(|_ctx, data: &mut AppData, _env| data.generate_print()),
+        );
+
+    Flex::row()
+        .with_child(palette())
+        .with_spacer(8.0)
+        .with_child(command_palette())
+        .with_spacer(8.0)
+        .with_child(
+            RadioGroup::new(vec![("Pencil", Tool::Pencil), ("Fill", Tool::Fill)])
+                .lens(AppData::tool),
+        )
+        .with_spacer(8.0)
+        .with_child(size)
+        .with_spacer(8.0)
+        .with_child(generate)
+        .with_spacer(8.0)
+        .with_child(
+            Flex::column()
+                .with_child(
+                    Button::new("Undo").on_click(|_ctx, data: &mut AppData, _env| data.undo()),
+                )
+                .with_child(
+                    Button::new("Redo").on_click(|_ctx, data: &mut AppData, _env| data.redo()),
+                )
+                .with_child(Button::new("Save").on_click(|ctx, _data, _env| {
+                    let options = FileDialogOptions::new()
+                        .allowed_types(vec![PNG])
+                        .default_type(PNG)
+                        .default_name("program.png");
+                    ctx.submit_command(SHOW_SAVE_PANEL.with(options));
+                }))
+                .with_child(Button::new("Open").on_click(|ctx, _data, _env| {
+                    let options = FileDialogOptions::new()
+                        .allowed_types(vec![PNG])
+                        .default_type(PNG);
+                    ctx.submit_command(SHOW_OPEN_PANEL.with(options));
+                }))
+                .with_child(Button::new("Assemble").on_click(|ctx, _data, _env| {
+                    let options = FileDialogOptions::new()
+                        .allowed_types(vec![ASSEMBLY])
+                        .default_type(ASSEMBLY);
+                    ctx.submit_command(SHOW_OPEN_PANEL.with(options));
+                }))
+                .with_child(Button::new("Export").on_click(|ctx, _data, _env| {
+                    let options = FileDialogOptions::new()
+                        .allowed_types(vec![LISTING, DOT, JSON, RUST, WAT, LINT, LINT_JSON])
+                        .default_type(LISTING)
+                        .default_name("program.lst");
+                    ctx.submit_command(SHOW_SAVE_PANEL.with(options));
+                })),
+        )
+        .padding(8.0)
+}
+
+/// Writes the program, its disassembly, its control flow graph, Rust or WebAssembly text
+/// compiled from it or what the linter makes of it out when a save dialog completes, and
+/// replaces it with the png or assembled source chosen when an open dialog does
+pub struct FileDialogs;
+
+impl<W: Widget<AppData>> Controller<AppData, W> for FileDialogs {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Command(cmd) if cmd.is(SAVE_FILE_AS) => {
+                let file = cmd.get_unchecked(SAVE_FILE_AS);
+                let image = &data.env.image;
+                let dialect = &data.env.dialect;
+                // what's written depends on which button opened the dialog
+                let lint = || {
+                    let (width, height) = (image.png_info.width, image.png_info.height);
+                    lint_pixels(
+                        width,
+                        height,
+                        image.bytes(),
+                        Some(image.codel_size),
+                        dialect,
+                    )
+                };
+                let disassembly = disassemble(image, dialect);
+                let cfg = || Cfg::new(&disassembly);
+                let name = file.path().to_string_lossy();
+                let contents = match file.path().extension().and_then(|ext| ext.to_str()) {
+                    _ if name.ends_with(".lint.json") => Ok(to_json(&lint()).into_bytes()),
+                    Some("lst") => Ok(disassembly.to_string().into_bytes()),
+                    Some("dot") | Some("gv") => Ok(cfg().to_dot().into_bytes()),
+                    Some("json") => Ok(cfg().to_json().into_bytes()),
+                    Some("rs") => to_rust(&cfg()).map(String::into_bytes),
+                    Some("wat") => to_wat(&cfg()).map(String::into_bytes),
+                    Some("txt") => Ok(to_text(&lint()).into_bytes()),
+                    _ => image.to_png().map_err(|e| e.to_string()),
+                };
+                let saved = contents.and_then(|contents| {
+                    std::fs::write(file.path(), contents).map_err(|e| e.to_string())
+                });
+                if let Err(e) = saved {
+                    tracing::error!("failed to save {}: {}", file.path().display(), e);
+                }
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(OPEN_FILE) => {
+                let file = cmd.get_unchecked(OPEN_FILE);
+                let png = file.path().extension().is_some_and(|ext| ext == "png");
+                let dialect = &data.env.dialect;
+                let opened = if png {
+                    std::fs::read(file.path())
+                        .map_err(|e| e.to_string())
+                        .and_then(|png| {
+                            // say what's wrong with every pixel, not just the first
+                            PietImg::from_png(&png).map_err(|e| {
+                                match lint_png(&png, None, dialect) {
+                                    Ok(diagnostics) => format!("{}\n{}", e, to_text(&diagnostics)),
+                                    Err(_) => e,
+                                }
+                            })
+                        })
+                } else {
+                    std::fs::read_to_string(file.path())
+                        .map_err(|e| e.to_string())
+                        .and_then(|source| assemble(&source).map_err(|e| e.to_string()))
+                };
+                match opened {
+                    Ok(after) => {
+                        let before = data.env.image.clone();
+                        data.edit(Edit::Replace { before, after });
+                    }
+                    Err(e) => tracing::error!("failed to open {}: {}", file.path().display(), e),
+                }
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
@@ -1,8 +1,9 @@
+use crate::dialect::{Dialect, Eof, InvalidChar, NegativeRoll, White};
 use crate::image::PietImg;
 use crate::ty::*;
-use std::sync::Arc;
+use std::collections::HashSet;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PietOp {
     None,
     Push,
@@ -29,34 +30,38 @@ pub enum PietOp {
     OutChar,
 }
 
-fn get_op(node: PietColor, next_node: PietColor) -> PietOp {
+/// The command for each change in hue (rows) and darkness (columns) between blocks
+pub const OPS: [[PietOp; 3]; 6] = [
+    [PietOp::None, PietOp::Push, PietOp::Pop],
+    [PietOp::Add, PietOp::Subtract, PietOp::Multiply],
+    [PietOp::Divide, PietOp::Mod, PietOp::Not],
+    [PietOp::Greater, PietOp::Pointer, PietOp::Switch],
+    [PietOp::Duplicate, PietOp::Roll, PietOp::InNumber],
+    [PietOp::InChar, PietOp::OutNumber, PietOp::OutChar],
+];
+
+pub fn get_op(node: PietColor, next_node: PietColor) -> PietOp {
     let color = &node.get_color_scale();
     let next_color = &next_node.get_color_scale();
     let darkness = (next_color.1 + 3 - color.1) % 3;
     let hue = (next_color.0 + 6 - color.0) % 6;
 
-    if darkness == 0 && hue == 0 {
-        PietOp::None
-    } else if darkness == 1 && hue == 0 {
-        PietOp::Push
-    } else if darkness == 2 && hue == 5 {
-        PietOp::OutChar
-    } else if darkness == 0 && hue == 4 {
-        PietOp::Duplicate
-    } else if darkness == 1 && hue == 2 {
-        PietOp::Multiply
-    } else {
-        panic!(
-            "Unknown transition of {:?} {:?} ({:?})",
-            node,
-            next_node,
-            (darkness, hue)
-        );
-    }
+    OPS[hue as usize][darkness as usize]
+}
+
+/// The colour to move into from `node` to execute `op`, the inverse of `get_op`
+pub fn get_op_color(node: PietColor, op: PietOp) -> PietColor {
+    let (hue, darkness) = OPS
+        .iter()
+        .enumerate()
+        .find_map(|(hue, row)| Some((hue, row.iter().position(|o| *o == op)?)))
+        .unwrap();
+    let (node_hue, node_lightness) = node.get_color_scale();
+    PietColor::from_color_scale(node_hue + hue as u32, node_lightness + darkness as u32)
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Stack(Vec<u32>);
+pub struct Stack(Vec<i64>);
 
 impl std::ops::DerefMut for Stack {
     fn deref_mut(&mut self) -> &mut Self::Target {
@@ -65,7 +70,7 @@ impl std::ops::DerefMut for Stack {
 }
 
 impl std::ops::Deref for Stack {
-    type Target = Vec<u32>;
+    type Target = Vec<i64>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -81,16 +86,54 @@ impl druid::Data for Stack {
 impl std::fmt::Display for Stack {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         for (i, x) in self.0.iter().rev().enumerate() {
-            if u8::is_ascii(&(*x as u8)) {
-                write!(f, "{}:\t{:#02X} ({})\n", i, x, char::from_u32(*x).unwrap())?;
-            } else {
-                write!(f, "{}:\t{:#02X}\n", i, x)?;
+            match u8::try_from(*x) {
+                Ok(c) if c.is_ascii_graphic() || c == b' ' => {
+                    writeln!(f, "{}:\t{:#02X} ({})", i, x, c as char)?
+                }
+                _ if *x < 0 => writeln!(f, "{}:\t{}", i, x)?,
+                _ => writeln!(f, "{}:\t{:#02X}", i, x)?,
             }
         }
         Ok(())
     }
 }
 
+/// Where the next step will go and what it will do, worked out without taking it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    /// The codel on the edge of the current block chosen by the DP and CC
+    pub exit: Codel,
+    /// The codel being travelled into, `None` if it's black or off the image
+    pub entry: Option<Codel>,
+    /// Number of codels in the current block, the value a push would use
+    pub block_size: u32,
+    /// The command executed on entering, `None` if the move is restricted
+    pub op: Option<PietOp>,
+}
+
+/// Whether the interpreter can keep taking steps
+#[derive(Clone, Debug, druid::Data, PartialEq)]
+pub enum ExecState {
+    Running,
+    /// An input command found nothing to read, stepping resumes once `PietEnv::input` has some
+    WaitingForInput,
+    /// Eight flow restrictions in a row, the program is over
+    Halted,
+    /// The interpreter couldn't carry on, the codel pointer is left on the offending block
+    Error(String),
+}
+
+impl std::fmt::Display for ExecState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExecState::Running => write!(f, "Running"),
+            ExecState::WaitingForInput => write!(f, "Waiting for input"),
+            ExecState::Halted => write!(f, "Halted"),
+            ExecState::Error(msg) => write!(f, "Error: {}", msg),
+        }
+    }
+}
+
 #[derive(Clone, druid::Lens, PartialEq)]
 pub struct PietEnv {
     /// Direction Pointer
@@ -107,18 +150,34 @@ pub struct PietEnv {
     pub flow_restricted_count: usize,
     /// Output
     pub output: String,
+    /// Input that hasn't been read by the program yet
+    pub input: String,
+    /// Input the program has already read, kept so it can be replayed
+    pub input_read: String,
+    /// How many steps have been taken since the start
+    pub steps: usize,
+    /// Running, halted or stuck on an error
+    pub state: ExecState,
+    /// Which interpreter's behaviour to follow where they differ
+    pub dialect: Dialect,
 }
 impl druid::Data for PietEnv {
     fn same(&self, other: &Self) -> bool {
         self.dp == other.dp
             && self.cp == other.cp
             && self.output == other.output
+            && self.input == other.input
             && self.cc == other.cc
+            && self.flow_restricted_count == other.flow_restricted_count
+            && self.stack == other.stack
+            && self.state == other.state
+            && self.steps == other.steps
+            && self.dialect == other.dialect
     }
 }
 
 impl PietEnv {
-    pub fn new(image: PietImg) -> Self {
+    pub fn new(image: PietImg, dialect: Dialect) -> Self {
         PietEnv {
             dp: DirectionPointer::Right,
             cc: CodelChoser::Left,
@@ -127,164 +186,380 @@ impl PietEnv {
             flow_restricted_count: 0,
             image,
             output: String::new(),
+            input: String::new(),
+            input_read: String::new(),
+            steps: 0,
+            state: ExecState::Running,
+            dialect,
         }
     }
 
-    /// Get all the codels on *disjoint* edge in no order
-    pub fn get_block_transition(&self, loc: Codel, dp: DirectionPointer) -> (Codel, u32) {
-        let flood_fill = self.image.get_codels_in_block(loc);
-        dbg!(&flood_fill.codels);
-        dbg!(dp);
-        dbg!(flood_fill.min_x, flood_fill.min_y);
-        dbg!(flood_fill.max_x, flood_fill.max_y);
+    /// Swap in a new program and run it from the start up to the step we were on, feeding it
+    /// the same input, or until it halts or gets stuck earlier
+    pub fn rerun(&mut self, image: PietImg) {
+        let steps = self.steps;
+        let input = std::mem::take(&mut self.input_read) + &self.input;
+        *self = PietEnv::new(image, self.dialect);
+        self.input = input;
+        while self.steps < steps && self.state == ExecState::Running {
+            self.step();
+        }
+    }
 
-        // 1. The interpreter finds the edge of the current colour block which is furthest in the direction of the DP. (This edge may be disjoint if the block is of a complex shape.)
-        let mut edge = vec![];
-        for node in &flood_fill.codels {
-            match dp {
-                DirectionPointer::Right => {
-                    if node.x == flood_fill.max_x {
-                        edge.push(node);
-                    }
-                }
-                DirectionPointer::Left => {
-                    if node.x == flood_fill.min_x {
-                        edge.push(node);
-                    }
-                }
-                DirectionPointer::Down => {
-                    if node.y == flood_fill.max_y {
-                        edge.push(node);
-                    }
-                }
-                DirectionPointer::Up => {
-                    if node.y == flood_fill.min_y {
-                        edge.push(node);
-                    }
-                }
+    /// Start the program over from the first codel
+    pub fn reset(&mut self) {
+        *self = PietEnv::new(self.image.clone(), self.dialect);
+    }
+
+    /// Replace the top two values with `f(second, top)`, leaving them alone if there aren't two
+    /// or `f` gives nothing
+    fn binary(&mut self, f: impl Fn(i64, i64) -> Option<i64>) {
+        if let [.., a, b] = **self.stack {
+            if let Some(val) = f(a, b) {
+                let len = self.stack.len();
+                self.stack.truncate(len - 2);
+                self.stack.push(val);
             }
         }
+    }
+
+    /// Read a character from the input, if there is one
+    fn read_char(&mut self) -> Option<char> {
+        let c = self.input.chars().next()?;
+        self.input_read.extend(self.input.drain(..c.len_utf8()));
+        Some(c)
+    }
+
+    /// Read a whitespace delimited, optionally signed integer from the input.
+    ///
+    /// Returns `None` if there's nothing but whitespace left to read. Anything that isn't a number
+    /// is consumed up to the next whitespace and read as `Some(None)`.
+    fn read_number(&mut self) -> Option<Option<i64>> {
+        let start = self.input.len() - self.input.trim_start().len();
+        if start == self.input.len() {
+            self.input.clear();
+            return None;
+        }
+        let end = self.input[start..]
+            .find(char::is_whitespace)
+            .map_or(self.input.len(), |end| start + end);
+        let number = self.input[start..end].parse().ok();
+        self.input_read.extend(self.input.drain(..end));
+        Some(number)
+    }
+
+    /// The codel a block is left from with the DP and CC pointing `dp` and `cc`, along with the
+    /// block's size
+    pub fn get_block_transition(
+        &self,
+        loc: Codel,
+        dp: DirectionPointer,
+        cc: CodelChoser,
+    ) -> (Codel, u32) {
+        let flood_fill = self.image.block(loc);
+
+        // 1. The interpreter finds the edge of the current colour block which is furthest in the direction of the DP. (This edge may be disjoint if the block is of a complex shape.)
+        let edge = flood_fill.codels.iter().filter(|node| match dp {
+            DirectionPointer::Right => node.x == flood_fill.max_x,
+            DirectionPointer::Left => node.x == flood_fill.min_x,
+            DirectionPointer::Down => node.y == flood_fill.max_y,
+            DirectionPointer::Up => node.y == flood_fill.min_y,
+        });
 
-        dbg!(&edge);
         // 2. The interpreter finds the codel of the current colour block on that edge which
         // is furthest to the CC's direction of the DP's direction of travel.
         // (Visualise this as standing on the program and walking in the direction of the DP; see table at right.)
-        let exit_node = if self.dp == DirectionPointer::Right && self.cc == CodelChoser::Left {
-            edge.sort_unstable_by(|a, b| a.y.partial_cmp(&b.y).unwrap());
-            // Uppermost
-            edge[0]
-        } else if self.dp == DirectionPointer::Right && self.cc == CodelChoser::Right {
-            edge.sort_unstable_by(|a, b| a.y.partial_cmp(&b.y).unwrap());
-            // Lowermost
-            edge.last().unwrap()
-        } else if self.dp == DirectionPointer::Down && self.cc == CodelChoser::Right {
-            edge.sort_unstable_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
-            // leftmost
-            edge[0]
-        } else if self.dp == DirectionPointer::Down && self.cc == CodelChoser::Left {
-            edge.sort_unstable_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
-            // rightmost
-            edge.last().unwrap()
-        } else if self.dp == DirectionPointer::Left && self.cc == CodelChoser::Left {
-            edge.sort_unstable_by(|a, b| a.y.partial_cmp(&b.y).unwrap());
-            // lowermost
-            edge.last().unwrap()
-        } else if self.dp == DirectionPointer::Left && self.cc == CodelChoser::Right {
-            edge.sort_unstable_by(|a, b| a.y.partial_cmp(&b.y).unwrap());
+        use CodelChoser::{Left, Right};
+        let exit_node = match (dp, cc) {
             // uppermost
-            edge[0]
-        } else if self.dp == DirectionPointer::Up && self.cc == CodelChoser::Left {
-            edge.sort_unstable_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+            (DirectionPointer::Right, Left) | (DirectionPointer::Left, Right) => {
+                edge.min_by_key(|node| node.y)
+            }
+            // lowermost
+            (DirectionPointer::Right, Right) | (DirectionPointer::Left, Left) => {
+                edge.max_by_key(|node| node.y)
+            }
             // leftmost
-            edge[0]
-        } else if self.dp == DirectionPointer::Up && self.cc == CodelChoser::Right {
-            edge.sort_unstable_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+            (DirectionPointer::Down, Right) | (DirectionPointer::Up, Left) => {
+                edge.min_by_key(|node| node.x)
+            }
             // rightmost
-            edge.last().unwrap()
-        } else {
-            todo!()
+            (DirectionPointer::Down, Left) | (DirectionPointer::Up, Right) => {
+                edge.max_by_key(|node| node.x)
+            }
         };
 
-        (*exit_node, flood_fill.codels.len() as u32)
+        (*exit_node.unwrap(), flood_fill.codels.len() as u32)
     }
 
-    pub fn step(&mut self) {
-        eprintln!("====== STEP ======");
-        let (exit_node, node_size) = self.get_block_transition(self.cp, self.dp);
+    /// Work out the move the next step will make from the current DP and CC
+    pub fn next_transition(&self) -> Transition {
+        let (exit, block_size) = self.get_block_transition(self.cp, self.dp, self.cc);
+        debug_assert_eq!(self.image.color(self.cp), self.image.color(exit));
 
-        let loc_color: PietColor = self.image[self.cp].into();
-        let node_color: PietColor = self.image[exit_node].into();
-        assert_eq!(loc_color, node_color);
         // The interpreter travels from that codel into the colour block containing the codel immediately in the direction of the DP.
-        let next_node = exit_node.block_in_dir(self.dp);
+        // White codels are slid across to whatever colour block is on the other side, unless
+        // the dialect stops in them
+        let entry = exit
+            .block_in_dir(self.dp)
+            .filter(|entry| self.image.contains(*entry))
+            .filter(|entry| self.image.color(*entry) != PietColor::Black)
+            .and_then(|entry| match self.image.color(entry) {
+                PietColor::White if self.dialect.white == White::Slide => {
+                    self.slide(entry).map(|(landing, _, _)| landing)
+                }
+                _ => Some(entry),
+            });
 
-        let next_node_color = if let Some(next_node) = next_node {
-            if self.image.contains(next_node) {
-                self.image[next_node].into()
+        let op = entry.map(|entry| {
+            let from = self.image.color(exit);
+            let to = self.image.color(entry);
+            if exit.block_in_dir(self.dp) != Some(entry)
+                || from == PietColor::White
+                || to == PietColor::White
+            {
+                PietOp::None
             } else {
-                PietColor::Black
+                get_op(from, to)
             }
-        } else {
-            PietColor::Black
-        };
+        });
 
-        if next_node_color == PietColor::Black {
-            if self.flow_restricted_count >= 8 {
-                return;
-            }
-            if self.flow_restricted_count % 2 == 0 {
-                match self.cc {
-                    CodelChoser::Left => self.cc = CodelChoser::Right,
-                    CodelChoser::Right => self.cc = CodelChoser::Left,
+        Transition {
+            exit,
+            entry,
+            block_size,
+            op,
+        }
+    }
+
+    /// Slide across white from `from` until reaching a colour block, toggling the CC and
+    /// turning the DP clockwise on every restriction. Returns the codel reached along with the
+    /// DP and CC it was reached with, or `None` if the path retraces itself and never gets out.
+    pub fn slide(&self, from: Codel) -> Option<(Codel, DirectionPointer, CodelChoser)> {
+        self.slide_path(from, |_| {})
+    }
+
+    /// Slide the way `slide` does, passing every white codel crossed and black one run into to
+    /// `visit`
+    pub fn slide_path(
+        &self,
+        from: Codel,
+        mut visit: impl FnMut(Codel),
+    ) -> Option<(Codel, DirectionPointer, CodelChoser)> {
+        let (mut codel, mut dp, mut cc) = (from, self.dp, self.cc);
+        let mut seen = HashSet::new();
+        visit(from);
+        while seen.insert((codel, dp)) {
+            match codel
+                .block_in_dir(dp)
+                .filter(|next| self.image.contains(*next))
+                .map(|next| (next, self.image.color(next)))
+            {
+                Some((next, PietColor::White)) => {
+                    visit(next);
+                    codel = next
                 }
-            } else if self.flow_restricted_count % 2 == 1 {
-                match self.dp {
-                    DirectionPointer::Right => self.dp = DirectionPointer::Down,
-                    DirectionPointer::Down => self.dp = DirectionPointer::Left,
-                    DirectionPointer::Left => self.dp = DirectionPointer::Up,
-                    DirectionPointer::Up => self.dp = DirectionPointer::Right,
+                Some((next, color)) if color != PietColor::Black => return Some((next, dp, cc)),
+                blocked => {
+                    if let Some((next, _)) = blocked {
+                        visit(next);
+                    }
+                    cc = cc.toggled();
+                    dp = dp.clockwise();
                 }
             }
+        }
+        None
+    }
+
+    pub fn step(&mut self) {
+        match self.state {
+            ExecState::Running => {}
+            ExecState::WaitingForInput if !self.input.is_empty() => self.state = ExecState::Running,
+            _ => return,
+        }
+        let Transition {
+            exit,
+            entry,
+            block_size,
+            op,
+        } = self.next_transition();
 
-            println!("BLACK RESTRICT #{}", self.flow_restricted_count);
-            self.flow_restricted_count += 1;
-            eprintln!(
-                "{:?} | {:?}/{:?} => {:?}/{:?} # CC {:?} # DP {:?}",
-                self.cp, exit_node, node_color, next_node, next_node_color, self.cc, self.dp,
-            );
+        if let Some(white) = exit
+            .block_in_dir(self.dp)
+            .filter(|_| self.dialect.white == White::Slide)
+            .filter(|next| self.image.contains(*next))
+            .filter(|next| self.image.color(*next) == PietColor::White)
+        {
+            match self.slide(white) {
+                Some((landing, dp, cc)) => {
+                    tracing::debug!("{:?} | {:?} => {:?} [slide]", self.cp, exit, landing);
+                    self.cp = landing;
+                    self.dp = dp;
+                    self.cc = cc;
+                    self.flow_restricted_count = 0;
+                    self.steps += 1;
+                }
+                // trapped in white, there's no way out
+                None => self.state = ExecState::Halted,
+            }
             return;
         }
 
-        // decode the transition
-        let op = get_op(node_color, next_node_color);
+        let op = match (entry, op) {
+            (Some(_), Some(op)) => op,
+            _ => {
+                match self.flow_restricted_count % 2 {
+                    0 => self.cc = self.cc.toggled(),
+                    _ => self.dp = self.dp.clockwise(),
+                }
+
+                self.flow_restricted_count += 1;
+                self.steps += 1;
+                tracing::debug!(
+                    "{:?} | {:?} => {:?} # CC {:?} # DP {:?} # RESTRICT {}",
+                    self.cp,
+                    exit,
+                    entry,
+                    self.cc,
+                    self.dp,
+                    self.flow_restricted_count,
+                );
+                if self.flow_restricted_count >= 8 {
+                    self.state = ExecState::Halted;
+                }
+                return;
+            }
+        };
+
         self.flow_restricted_count = 0;
-        eprintln!(
-            "{:?} | {:?}/{:?} => {:?}/{:?} [{:?}]",
-            self.cp, exit_node, node_color, next_node, next_node_color, op
-        );
+        tracing::debug!("{:?} | {:?} => {:?} [{:?}]", self.cp, exit, entry, op);
+        if !self.execute(op, block_size) {
+            return;
+        }
+        self.cp = entry.unwrap();
+        self.steps += 1;
+    }
 
+    /// Run `op` as if leaving a block of `block_size` codels. Returns false if it stopped the
+    /// program, waiting for input or on an error, in which case it's to be run again rather than
+    /// moving on.
+    pub fn execute(&mut self, op: PietOp, block_size: u32) -> bool {
+        // Operations without enough values on the stack are ignored, as the spec requires
         match op {
-            PietOp::Push => self.stack.push(node_size),
+            PietOp::Push => self.stack.push(block_size as i64),
+            PietOp::InChar => match self.read_char() {
+                Some(c) => self.stack.push(c as i64),
+                None => return self.end_of_input(),
+            },
+            PietOp::InNumber => match self.read_number() {
+                Some(Some(val)) => self.stack.push(val),
+                // not a number, ignore the command
+                Some(None) => {}
+                None => return self.end_of_input(),
+            },
             PietOp::OutChar => {
-                let val = self.stack.pop().unwrap();
-                self.output.push(char::from_u32(val).unwrap().into());
+                if let Some(val) = self.stack.pop() {
+                    match u32::try_from(val).ok().and_then(char::from_u32) {
+                        Some(c) => self.output.push(c),
+                        None => match self.dialect.invalid_char {
+                            InvalidChar::Error => {
+                                self.stack.push(val);
+                                self.state = ExecState::Error(format!(
+                                    "{:#X} is not a valid character",
+                                    val
+                                ));
+                                return false;
+                            }
+                            InvalidChar::Ignore => {}
+                            InvalidChar::Replace => self.output.push(char::REPLACEMENT_CHARACTER),
+                        },
+                    }
+                }
             }
             PietOp::Duplicate => {
-                let val = self.stack.pop().unwrap();
-                self.stack.push(val);
-                self.stack.push(val);
+                if let Some(&val) = self.stack.last() {
+                    self.stack.push(val);
+                }
             }
-            PietOp::Multiply => {
-                let a = self.stack.pop().unwrap();
-                let b = self.stack.pop().unwrap();
-                self.stack.push(a * b);
+            PietOp::Multiply => self.binary(|a, b| Some(a.wrapping_mul(b))),
+            PietOp::None => {}
+            PietOp::Pop => {
+                self.stack.pop();
             }
-            _ => {
-                self.output.push_str(format!("UNKNOWN OP: {:?}", op).as_str());
-                todo!()
+            PietOp::Add => self.binary(|a, b| Some(a.wrapping_add(b))),
+            PietOp::Subtract => self.binary(|a, b| Some(a.wrapping_sub(b))),
+            // by zero is ignored
+            PietOp::Divide => {
+                let dialect = self.dialect;
+                self.binary(|a, b| dialect.divide(a, b))
+            }
+            PietOp::Mod => {
+                let dialect = self.dialect;
+                self.binary(|a, b| dialect.remainder(a, b))
+            }
+            PietOp::Not => {
+                if let Some(val) = self.stack.last_mut() {
+                    *val = (*val == 0) as i64;
+                }
+            }
+            PietOp::Greater => self.binary(|a, b| Some((a > b) as i64)),
+            PietOp::Pointer => {
+                if let Some(val) = self.stack.pop() {
+                    for _ in 0..val.rem_euclid(4) {
+                        self.dp = self.dp.clockwise();
+                    }
+                }
+            }
+            PietOp::Switch => {
+                if let Some(val) = self.stack.pop() {
+                    if val.rem_euclid(2) == 1 {
+                        self.cc = self.cc.toggled();
+                    }
+                }
+            }
+            PietOp::Roll => {
+                if self.stack.len() >= 2 {
+                    let rolls = self.stack[self.stack.len() - 1];
+                    let depth = self.stack[self.stack.len() - 2];
+                    // a depth deeper than the stack is ignored
+                    let len = self.stack.len() - 2;
+                    if depth < 0 && self.dialect.negative_roll == NegativeRoll::Error {
+                        self.state = ExecState::Error(format!(
+                            "can't roll to a negative depth of {}",
+                            depth
+                        ));
+                        return false;
+                    }
+                    if depth >= 0 && depth as usize <= len {
+                        self.stack.truncate(len);
+                        if depth > 0 {
+                            let start = len - depth as usize;
+                            self.stack[start..].rotate_right(rolls.rem_euclid(depth) as usize);
+                        }
+                    }
+                }
+            }
+            PietOp::OutNumber => {
+                if let Some(val) = self.stack.pop() {
+                    self.output.push_str(&val.to_string());
+                }
             }
         }
+        true
+    }
 
-        self.cp = next_node.unwrap();
+    /// Carry on from an input command with nothing left to read however the dialect says,
+    /// returning false if it's to wait for more
+    fn end_of_input(&mut self) -> bool {
+        match self.dialect.eof {
+            Eof::Wait => {
+                self.state = ExecState::WaitingForInput;
+                return false;
+            }
+            Eof::Ignore => {}
+            Eof::Push(val) => self.stack.push(val),
+        }
+        true
     }
 }
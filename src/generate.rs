@@ -0,0 +1,49 @@
+use crate::env::PietOp;
+use crate::image::PietImg;
+use crate::layout::{lay_out, Layout};
+use crate::synth::{cost, Metric, Plan, Synth};
+
+/// Commands that print `text`, each with the size of the block it's executed from. The last
+/// character printed stays on the stack, and the next is made by adding to or subtracting from
+/// it when that's cheaper than starting over.
+pub fn print_ops(text: &str) -> Plan {
+    let mut synth = Synth::new(Metric::Area);
+    let mut ops = vec![];
+    let mut last: Option<u32> = None;
+    for c in text.chars() {
+        let c = c as u32;
+        let fresh = match last {
+            Some(_) => [vec![(PietOp::Pop, 1)], synth.plan(c as i64)].concat(),
+            None => synth.plan(c as i64),
+        };
+        let code = match last {
+            Some(last) if last == c => vec![],
+            Some(last) => {
+                let relative = match c.cmp(&last) {
+                    std::cmp::Ordering::Greater => {
+                        [synth.plan((c - last) as i64), vec![(PietOp::Add, 1)]].concat()
+                    }
+                    _ => [synth.plan((last - c) as i64), vec![(PietOp::Subtract, 1)]].concat(),
+                };
+                if cost(Metric::Area, &relative) < cost(Metric::Area, &fresh) {
+                    relative
+                } else {
+                    fresh
+                }
+            }
+            None => fresh,
+        };
+        ops.extend(code);
+        ops.extend([(PietOp::Duplicate, 1), (PietOp::OutChar, 1)]);
+        last = Some(c);
+    }
+    if last.is_some() {
+        ops.push((PietOp::Pop, 1));
+    }
+    ops
+}
+
+/// A program that prints `text` and halts, laid out with `layout`
+pub fn print_program(text: &str, layout: Layout) -> PietImg {
+    lay_out(&print_ops(text), layout)
+}
@@ -0,0 +1,174 @@
+use crate::image::PietImg;
+use crate::ty::{Codel, PietColor};
+use crate::AppData;
+use druid::im::Vector;
+use druid::widget::Controller;
+use druid::{Data, Env, Event, EventCtx, HotKey, SysMods, Widget};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A change made to a program, with enough kept to take it back
+#[derive(Clone, PartialEq)]
+pub enum Edit {
+    /// Codels recoloured, each with its colour before and after
+    Paint(Vec<(Codel, PietColor, PietColor)>),
+    /// The whole program swapped out, by resizing the canvas or assembling a new one. Cropped
+    /// codels can't be recovered from the new image so both are kept whole
+    Replace { before: PietImg, after: PietImg },
+}
+
+impl Edit {
+    pub fn apply(&self, image: &mut PietImg) {
+        match self {
+            Edit::Paint(codels) => {
+                for (codel, _, after) in codels {
+                    image.set(*codel, *after);
+                }
+            }
+            Edit::Replace { after, .. } => *image = after.clone(),
+        }
+    }
+
+    pub fn revert(&self, image: &mut PietImg) {
+        match self {
+            Edit::Paint(codels) => {
+                for (codel, before, _) in codels.iter().rev() {
+                    image.set(*codel, *before);
+                }
+            }
+            Edit::Replace { before, .. } => *image = before.clone(),
+        }
+    }
+
+    /// Fold a later paint into this one so they're undone together
+    fn merge(&mut self, later: &Edit) -> bool {
+        let (codels, later) = match (self, later) {
+            (Edit::Paint(codels), Edit::Paint(later)) => (codels, later),
+            _ => return false,
+        };
+        let index: HashMap<Codel, usize> = codels
+            .iter()
+            .enumerate()
+            .map(|(i, (codel, _, _))| (*codel, i))
+            .collect();
+        for (codel, before, after) in later {
+            match index.get(codel) {
+                Some(i) => codels[*i].2 = *after,
+                None => codels.push((*codel, *before, *after)),
+            }
+        }
+        true
+    }
+}
+
+/// Undo and redo stacks of edits
+#[derive(Clone, Data, Default)]
+pub struct History {
+    undo: Vector<Arc<Edit>>,
+    redo: Vector<Arc<Edit>>,
+    /// Edits are being merged into one, like the codels of a single brush stroke
+    grouping: bool,
+    /// The top of the undo stack belongs to the open group
+    group_started: bool,
+}
+
+impl History {
+    /// Merge everything recorded until `end_group` into a single edit
+    pub fn begin_group(&mut self) {
+        self.grouping = true;
+        self.group_started = false;
+    }
+
+    pub fn end_group(&mut self) {
+        self.grouping = false;
+        self.group_started = false;
+    }
+
+    /// Whether a group's been begun and not yet ended
+    pub fn in_group(&self) -> bool {
+        self.grouping
+    }
+
+    /// Remember an edit that's already been applied, forgetting anything that was undone
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+        if self.group_started {
+            if let Some(last) = self.undo.back_mut() {
+                if Arc::make_mut(last).merge(&edit) {
+                    return;
+                }
+            }
+        }
+        self.undo.push_back(Arc::new(edit));
+        self.group_started = self.grouping;
+    }
+
+    /// Take back the last edit, returning the program as it was before it
+    pub fn undo(&mut self, image: &PietImg) -> Option<PietImg> {
+        let edit = self.undo.pop_back()?;
+        let mut image = image.clone();
+        edit.revert(&mut image);
+        self.redo.push_back(edit);
+        self.group_started = false;
+        Some(image)
+    }
+
+    /// Make the last undone edit again, returning the program with it applied
+    pub fn redo(&mut self, image: &PietImg) -> Option<PietImg> {
+        let edit = self.redo.pop_back()?;
+        let mut image = image.clone();
+        edit.apply(&mut image);
+        self.undo.push_back(edit);
+        self.group_started = false;
+        Some(image)
+    }
+}
+
+impl AppData {
+    pub fn undo(&mut self) {
+        if let Some(image) = self.history.undo(&self.env.image) {
+            self.replace_image(image);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(image) = self.history.redo(&self.env.image) {
+            self.replace_image(image);
+        }
+    }
+}
+
+/// Undo and redo from the keyboard while editing, once the program's been clicked on so text
+/// boxes keep their own undo
+pub struct Shortcuts;
+
+impl<W: Widget<AppData>> Controller<AppData, W> for Shortcuts {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseDown(_) if data.drawing => ctx.request_focus(),
+            Event::KeyDown(key) if data.drawing => {
+                if HotKey::new(SysMods::Cmd, "z").matches(key) {
+                    data.undo();
+                    ctx.set_handled();
+                    return;
+                }
+                if HotKey::new(SysMods::CmdShift, "Z").matches(key)
+                    || HotKey::new(SysMods::Cmd, "y").matches(key)
+                {
+                    data.redo();
+                    ctx.set_handled();
+                    return;
+                }
+            }
+            _ => {}
+        }
+        child.event(ctx, event, data, env)
+    }
+}
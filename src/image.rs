@@ -1,6 +1,6 @@
 use crate::ty::*;
-use std::collections::VecDeque;
-use std::sync::Arc;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, OnceLock};
 
 #[derive(druid::Data, PartialEq)]
 pub struct OutputInfo(std::sync::Arc<png::OutputInfo>);
@@ -28,34 +28,203 @@ pub struct FloodFill {
     pub max_y: u32,
 }
 
-#[derive(Clone, druid::Data, PartialEq)]
+/// Every codel of a program labelled with the colour block it's part of
+pub struct Blocks {
+    /// Index into `blocks` of each codel, row by row
+    labels: Vec<usize>,
+    pub blocks: Vec<FloodFill>,
+}
+
+impl Blocks {
+    fn new(image: &PietImg) -> Self {
+        let (width, height) = (image.png_info.width, image.png_info.height);
+        let mut labels = vec![usize::MAX; (width * height) as usize];
+        let mut blocks = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                if labels[(y * width + x) as usize] != usize::MAX {
+                    continue;
+                }
+                let block = image.get_codels_in_block(Codel::new(x, y));
+                for codel in &block.codels {
+                    labels[(codel.y * width + codel.x) as usize] = blocks.len();
+                }
+                blocks.push(block);
+            }
+        }
+        Blocks { labels, blocks }
+    }
+}
+
+#[derive(Clone, druid::Data)]
 pub struct PietImg {
     pub codel_size: u32,
     pub png_info: OutputInfo,
     bytes: std::sync::Arc<Vec<u8>>,
+    /// Built the first time a block is looked up, and thrown away whenever a codel changes
+    #[data(ignore)]
+    blocks: Arc<OnceLock<Blocks>>,
 }
 
-impl PietImg {
-    pub fn new(codel_size: u32, png_info: png::OutputInfo, bytes: &[u8]) -> Self {
-        verify_colors(bytes);
+impl PartialEq for PietImg {
+    fn eq(&self, other: &Self) -> bool {
+        self.codel_size == other.codel_size
+            && self.png_info == other.png_info
+            && self.bytes == other.bytes
+    }
+}
 
-        // being lazy for now
-        assert!(codel_size == 1);
+/// Describe a headerless 8 bit RGB image, the only format we decode pngs to
+fn rgb_info(width: u32, height: u32) -> png::OutputInfo {
+    png::OutputInfo {
+        width,
+        height,
+        color_type: png::ColorType::Rgb,
+        bit_depth: png::BitDepth::Eight,
+        line_size: width as usize * 3,
+    }
+}
+
+/// Decode a png to 8 bit RGB, row by row, dropping any alpha
+pub fn decode_png(png: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
+    let mut decoder = png::Decoder::new(png);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    let pixels = buf[..info.buffer_size()].chunks(info.color_type.samples());
+    let rgb = match info.color_type {
+        png::ColorType::Rgb | png::ColorType::Rgba => {
+            pixels.flat_map(|p| [p[0], p[1], p[2]]).collect()
+        }
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+            pixels.flat_map(|p| [p[0], p[0], p[0]]).collect()
+        }
+        png::ColorType::Indexed => return Err("palette wasn't expanded".to_string()),
+    };
+    Ok((info.width, info.height, rgb))
+}
+
+/// The largest size that every codel of an image given as 8 bit RGB is a single colour at
+pub fn codel_size(width: u32, height: u32, rgb: &[u8]) -> u32 {
+    let pixel = |x: u32, y: u32| &rgb[((y * width + x) * 3) as usize..][..3];
+    (2..=width.min(height))
+        .rev()
+        .filter(|size| width.is_multiple_of(*size) && height.is_multiple_of(*size))
+        .find(|&size| {
+            (0..height)
+                .all(|y| (0..width).all(|x| pixel(x, y) == pixel(x - x % size, y - y % size)))
+        })
+        .unwrap_or(1)
+}
+
+impl PietImg {
+    pub fn new(codel_size: u32, png_info: png::OutputInfo, bytes: &[u8]) -> Result<Self, String> {
+        verify_colors(png_info.width, bytes)?;
+        if codel_size != 1 {
+            return Err(format!(
+                "codels of {0}x{0} pixels have to be scaled down first",
+                codel_size
+            ));
+        }
+        Ok(PietImg::from_rgb(png_info, bytes))
+    }
 
+    /// A program from RGB that's known to be all Piet colours
+    fn from_rgb(png_info: png::OutputInfo, bytes: &[u8]) -> Self {
         PietImg {
-            codel_size,
+            codel_size: 1,
             png_info: OutputInfo(Arc::new(png_info)),
             bytes: std::sync::Arc::new(bytes.to_vec()),
+            blocks: Arc::default(),
         }
     }
 
+    /// Load a program from a png, scaled down to one pixel a codel if every codel's a single
+    /// colour at some larger size
+    pub fn from_png(png: &[u8]) -> Result<Self, String> {
+        let (width, height, rgb) = decode_png(png)?;
+        let size = codel_size(width, height, &rgb);
+        let (width, height) = (width / size, height / size);
+        let mut bytes = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let i = ((y * size * width * size + x * size) * 3) as usize;
+                bytes.extend_from_slice(&rgb[i..i + 3]);
+            }
+        }
+        PietImg::new(1, rgb_info(width, height), &bytes)
+    }
+
     pub fn contains(&self, loc: Codel) -> bool {
         loc.x < self.png_info.width && loc.y < self.png_info.height
     }
 
+    pub fn color(&self, loc: Codel) -> PietColor {
+        self[loc].into()
+    }
+
+    /// The raw RGB bytes, row by row
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// A program made up of a single colour
+    pub fn filled(width: u32, height: u32, color: PietColor) -> Self {
+        let bytes = color.rgb().repeat((width * height) as usize);
+        PietImg::from_rgb(rgb_info(width, height), &bytes)
+    }
+
+    pub fn set(&mut self, loc: Codel, color: PietColor) {
+        assert!(self.contains(loc));
+        let i = ((loc.y * self.png_info.width + loc.x) * 3) as usize;
+        // the bytes are always shared with the previous app state, so this copies
+        // and `Data::same` sees the change
+        Arc::make_mut(&mut self.bytes)[i..i + 3].copy_from_slice(&color.rgb());
+        self.blocks = Arc::default();
+    }
+
+    /// Crop or grow the program from the bottom right corner, padding with white
+    pub fn resized(&self, width: u32, height: u32) -> Self {
+        let mut resized = PietImg::filled(width, height, PietColor::White);
+        for y in 0..height.min(self.png_info.height) {
+            for x in 0..width.min(self.png_info.width) {
+                let codel = Codel::new(x, y);
+                resized.set(codel, self.color(codel));
+            }
+        }
+        resized
+    }
+
+    /// Encode the program as a png with one pixel per codel
+    pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, self.png_info.width, self.png_info.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.bytes)?;
+        Ok(png)
+    }
+
+    /// The program broken up into colour blocks
+    pub fn blocks(&self) -> &Blocks {
+        self.blocks.get_or_init(|| Blocks::new(self))
+    }
+
+    /// The block containing `loc`, like `get_codels_in_block` without the flood fill
+    pub fn block(&self, loc: Codel) -> &FloodFill {
+        &self.blocks().blocks[self.block_id(loc)]
+    }
+
+    /// Index into `Blocks::blocks` of the block containing `loc`
+    pub fn block_id(&self, loc: Codel) -> usize {
+        self.blocks().labels[(loc.y * self.png_info.width + loc.x) as usize]
+    }
+
     pub fn get_codels_in_block(&self, loc: Codel) -> FloodFill {
         let mut queue = VecDeque::new();
-        let mut seen: Vec<Codel> = vec![];
+        let mut seen: HashSet<Codel> = HashSet::new();
+        let mut codels = vec![];
         let mut min_y = self.png_info.height;
         let mut max_y = 0;
         let mut min_x = self.png_info.width;
@@ -66,7 +235,7 @@ impl PietImg {
 
         while !queue.is_empty() {
             let n = queue.pop_front().unwrap();
-            if seen.contains(&n) {
+            if !seen.insert(n) {
                 continue;
             }
             let codel_color: PietColor = self[n].into();
@@ -86,7 +255,7 @@ impl PietImg {
             if n.y < min_y {
                 min_y = n.y;
             }
-            seen.push(n);
+            codels.push(n);
 
             if n.x > 0 {
                 let left = Codel::new(n.x - 1, n.y);
@@ -114,7 +283,7 @@ impl PietImg {
         }
 
         FloodFill {
-            codels: seen,
+            codels,
             max_x,
             min_x,
             max_y,
@@ -138,9 +307,17 @@ impl std::ops::Index<Codel> for PietImg {
 }
 
 /// Ensure there are no colours we don't know how to handle in the png
-fn verify_colors(bytes: &[u8]) {
-    for chunk in bytes[..].chunks(3) {
+fn verify_colors(width: u32, bytes: &[u8]) -> Result<(), String> {
+    for (i, chunk) in bytes.chunks(3).enumerate() {
         let sample = u32::from_be_bytes([0x0, chunk[0], chunk[1], chunk[2]]);
-        let _: PietColor = num::FromPrimitive::from_u32(sample).unwrap();
+        let color: Option<PietColor> = num::FromPrimitive::from_u32(sample);
+        if color.is_none() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            return Err(format!(
+                "the pixel at ({}, {}) is #{:06X}, which isn't a Piet colour",
+                x, y, sample
+            ));
+        }
     }
+    Ok(())
 }
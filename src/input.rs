@@ -0,0 +1,34 @@
+use crate::AppData;
+use druid::widget::Controller;
+use druid::{Env, Event, EventCtx, KbKey, Widget};
+
+impl AppData {
+    /// Hand the line being typed over to the program, newline terminated
+    pub fn send_input(&mut self) {
+        self.env.input.push_str(&self.input_line);
+        self.env.input.push('\n');
+        self.input_line.clear();
+    }
+}
+
+/// Sends the input line when enter is pressed in the text box
+pub struct SendOnEnter;
+
+impl<W: Widget<AppData>> Controller<AppData, W> for SendOnEnter {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        match event {
+            Event::KeyDown(key) if key.key == KbKey::Enter => {
+                data.send_input();
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::env::get_op;
+use crate::image::PietImg;
+use crate::ty::*;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+const HUES: [&str; 6] = ["red", "yellow", "green", "cyan", "blue", "magenta"];
+const LIGHTNESSES: [&str; 3] = ["light", "normal", "dark"];
+
+/// Everything worth knowing about a codel while reading a program: its colour, the block it's
+/// part of and the command taken when leaving that block into each of its neighbours.
+pub fn describe(image: &PietImg, codel: Codel) -> String {
+    let mut out = String::new();
+    let color = image.color(codel);
+    writeln!(out, "Codel ({}, {})", codel.x, codel.y).unwrap();
+    match color {
+        PietColor::Black => {
+            writeln!(out, "Black, restricts movement").unwrap();
+            return out;
+        }
+        PietColor::White => writeln!(out, "White, passed through without a command").unwrap(),
+        _ => {
+            let (hue, lightness) = color.get_color_scale();
+            writeln!(
+                out,
+                "{:?}, hue {} ({}) lightness {} ({})",
+                color, hue, HUES[hue as usize], lightness, LIGHTNESSES[lightness as usize]
+            )
+            .unwrap();
+        }
+    }
+
+    let block = image.block(codel);
+    writeln!(
+        out,
+        "Block of {} codels, ({}, {}) to ({}, {})",
+        block.codels.len(),
+        block.min_x,
+        block.min_y,
+        block.max_x,
+        block.max_y
+    )
+    .unwrap();
+
+    writeln!(out, "\nExits into").unwrap();
+    let members: HashSet<Codel> = block.codels.iter().copied().collect();
+    let mut seen = HashSet::new();
+    for codel in &block.codels {
+        for dir in &[
+            DirectionPointer::Right,
+            DirectionPointer::Down,
+            DirectionPointer::Left,
+            DirectionPointer::Up,
+        ] {
+            let next = match codel.block_in_dir(*dir) {
+                Some(next) if image.contains(next) && !members.contains(&next) => next,
+                _ => continue,
+            };
+            if seen.contains(&next) {
+                continue;
+            }
+            let neighbour = image.block(next);
+            seen.extend(neighbour.codels.iter().copied());
+
+            let next_color = image.color(next);
+            let command = match (color, next_color) {
+                (_, PietColor::Black) => "restricted".to_string(),
+                (PietColor::White, _) | (_, PietColor::White) => "none".to_string(),
+                _ => format!("{:?}", get_op(color, next_color)),
+            };
+            writeln!(
+                out,
+                "  {:?} at ({}, {}): {}",
+                next_color, next.x, next.y, command
+            )
+            .unwrap();
+        }
+    }
+    out
+}
@@ -0,0 +1,286 @@
+use crate::cfg::{Cfg, State};
+use crate::dialect::White;
+use crate::env::{ExecState, PietEnv, PietOp};
+use crate::ty::Codel;
+use std::collections::HashMap;
+
+/// A command run leaving one state of a basic block for the next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Command {
+    /// The state it leaves
+    pub state: usize,
+    pub op: PietOp,
+    /// Size of the block being left, the value a push uses
+    pub size: u32,
+}
+
+/// How a basic block ends
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exit {
+    /// The last command goes into the start of another basic block
+    Jump(usize),
+    /// A pointer or switch leaving `state` pops a value and goes to the target it picks,
+    /// modulo how many there are
+    Branch { state: usize, targets: Vec<usize> },
+    /// Every way out of `state` is restricted, so it's left for the start of `toggle` with the
+    /// CC toggled or `turn` with the DP turned, depending on how many times in a row that's
+    /// happened
+    Blocked {
+        state: usize,
+        toggle: usize,
+        turn: usize,
+    },
+    /// Leaving `state` slides into white that never leads anywhere
+    Trapped(usize),
+}
+
+/// A run of commands that always follow each other, from one state with more than one way in
+/// up to one with a choice of ways out
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub body: Vec<Command>,
+    pub exit: Exit,
+}
+
+/// A program as basic blocks, the first where it starts
+#[derive(Debug, Clone)]
+pub struct Ir {
+    /// The control flow graph's states, which commands and exits refer to
+    pub states: Vec<State>,
+    /// The first codel of the colour block each state leaves
+    pub codels: Vec<Codel>,
+    pub blocks: Vec<BasicBlock>,
+    /// The basic block each state is in and how far into its body, if it's reachable
+    positions: Vec<Option<(usize, usize)>>,
+}
+
+impl Ir {
+    /// Fuse the states of `cfg` into basic blocks. A state starts one if it's where the program
+    /// starts or isn't only ever reached by a single command from another state.
+    pub fn new(cfg: &Cfg) -> Self {
+        let edges = cfg.edges_from();
+        let mut into = vec![0; cfg.states.len()];
+        let mut leaders = vec![false; cfg.states.len()];
+        if !leaders.is_empty() {
+            leaders[0] = true;
+        }
+        for edge in &cfg.edges {
+            into[edge.to] += 1;
+            if edges[edge.from].len() > 1 {
+                leaders[edge.to] = true;
+            }
+        }
+        for (state, into) in into.into_iter().enumerate() {
+            if into != 1 {
+                leaders[state] = true;
+            }
+        }
+        let starts: HashMap<usize, usize> = (0..cfg.states.len())
+            .filter(|state| leaders[*state])
+            .enumerate()
+            .map(|(block, state)| (state, block))
+            .collect();
+
+        let mut blocks = vec![];
+        for start in (0..cfg.states.len()).filter(|state| leaders[*state]) {
+            let mut body = vec![];
+            let mut state = start;
+            let exit = loop {
+                match edges[state][..] {
+                    [] => break Exit::Trapped(state),
+                    [toggle, turn] if toggle.command.is_none() => {
+                        break Exit::Blocked {
+                            state,
+                            toggle: starts[&toggle.to],
+                            turn: starts[&turn.to],
+                        }
+                    }
+                    [edge] => {
+                        body.push(Command {
+                            state,
+                            op: edge.command.unwrap(),
+                            size: cfg.block(state).size as u32,
+                        });
+                        match starts.get(&edge.to) {
+                            Some(to) => break Exit::Jump(*to),
+                            None => state = edge.to,
+                        }
+                    }
+                    ref branches => {
+                        break Exit::Branch {
+                            state,
+                            targets: branches.iter().map(|edge| starts[&edge.to]).collect(),
+                        }
+                    }
+                }
+            };
+            blocks.push(BasicBlock { body, exit });
+        }
+        let mut positions = vec![None; cfg.states.len()];
+        for (index, block) in blocks.iter().enumerate() {
+            for (command, Command { state, .. }) in block.body.iter().enumerate() {
+                positions[*state] = Some((index, command));
+            }
+            if let Exit::Branch { state, .. } | Exit::Blocked { state, .. } | Exit::Trapped(state) =
+                block.exit
+            {
+                positions[state] = Some((index, block.body.len()));
+            }
+        }
+        Ir {
+            states: cfg.states.clone(),
+            codels: (0..cfg.states.len())
+                .map(|state| cfg.block(state).codel)
+                .collect(),
+            blocks,
+            positions,
+        }
+    }
+}
+
+/// Runs a program's IR, keeping its stack, I/O and how it's getting on in a `PietEnv` so
+/// commands behave exactly as they do when stepping through colours
+pub struct IrEnv<'a> {
+    ir: &'a Ir,
+    block: usize,
+    /// Index of the next command in the block's body, its exit once past the last
+    command: usize,
+    /// Everything but where the program is, with the DP and CC kept up to date
+    pub env: PietEnv,
+}
+
+impl<'a> IrEnv<'a> {
+    /// Carry on from wherever `env` has got to, if it's somewhere the IR goes. The IR slides
+    /// across white, so it can't carry on a dialect that doesn't.
+    pub fn resume(ir: &'a Ir, env: PietEnv) -> Option<Self> {
+        if env.dialect.white != White::Slide {
+            return None;
+        }
+        let block = env.image.block_id(env.cp);
+        let state = (0..ir.states.len()).find(|state| {
+            let State { dp, cc, .. } = ir.states[*state];
+            (dp, cc) == (env.dp, env.cc) && env.image.block_id(ir.codels[*state]) == block
+        })?;
+        let (block, command) = ir.positions[state]?;
+        Some(IrEnv {
+            ir,
+            block,
+            command,
+            env,
+        })
+    }
+
+    /// Where the program's got to as a `PietEnv`, its codel pointer on the first codel of the
+    /// colour block it's in unless it's already somewhere in it
+    pub fn into_env(self) -> PietEnv {
+        let state = self.ir.blocks.get(self.block).map(|_| self.state());
+        let mut env = self.env;
+        if let Some(state) = state {
+            let codel = self.ir.codels[state];
+            if env.image.block_id(env.cp) != env.image.block_id(codel) {
+                env.cp = codel;
+            }
+        }
+        env
+    }
+
+    /// The control flow graph state the program is in
+    pub fn state(&self) -> usize {
+        let block = &self.ir.blocks[self.block];
+        match (block.body.get(self.command), &block.exit) {
+            (Some(command), _) => command.state,
+            (None, Exit::Branch { state, .. })
+            | (None, Exit::Blocked { state, .. })
+            | (None, Exit::Trapped(state)) => *state,
+            (None, Exit::Jump(_)) => unreachable!("jumps are taken with the last command"),
+        }
+    }
+
+    fn enter(&mut self, block: usize) {
+        self.block = block;
+        self.command = 0;
+    }
+
+    /// Take the same step `PietEnv::step` would
+    pub fn step(&mut self) {
+        match self.env.state {
+            ExecState::Running => {}
+            ExecState::WaitingForInput if !self.env.input.is_empty() => {
+                self.env.state = ExecState::Running
+            }
+            _ => return,
+        }
+        let Some(block) = self.ir.blocks.get(self.block) else {
+            self.env.state = ExecState::Halted;
+            return;
+        };
+        match (block.body.get(self.command), &block.exit) {
+            (Some(command), exit) => {
+                self.env.flow_restricted_count = 0;
+                if !self.env.execute(command.op, command.size) {
+                    return;
+                }
+                self.command += 1;
+                if let (true, Exit::Jump(to)) = (self.command == block.body.len(), exit) {
+                    self.enter(*to);
+                }
+            }
+            (None, Exit::Branch { targets, .. }) => {
+                self.env.flow_restricted_count = 0;
+                let n = self
+                    .env
+                    .stack
+                    .pop()
+                    .map_or(0, |val| val.rem_euclid(targets.len() as i64));
+                self.enter(targets[n as usize]);
+            }
+            (None, Exit::Blocked { toggle, turn, .. }) => {
+                let to = match self.env.flow_restricted_count % 2 {
+                    0 => *toggle,
+                    _ => *turn,
+                };
+                self.env.flow_restricted_count += 1;
+                self.enter(to);
+                if self.env.flow_restricted_count >= 8 {
+                    self.env.state = ExecState::Halted;
+                }
+            }
+            (None, Exit::Trapped(_)) => {
+                self.env.state = ExecState::Halted;
+                return;
+            }
+            (None, Exit::Jump(_)) => unreachable!("jumps are taken with the last command"),
+        }
+        self.env.steps += 1;
+        let state = self.ir.states[self.state()];
+        self.env.dp = state.dp;
+        self.env.cc = state.cc;
+    }
+
+    /// Describe the first way this isn't where `expected`, stepped through colours, is: in the
+    /// same colour block with the same DP, CC, stack, output and state
+    pub fn compare(&self, expected: &PietEnv) -> Result<(), String> {
+        let env = &self.env;
+        let block = self.ir.codels[self.state()];
+        let differences = [
+            (
+                "block",
+                env.image.block_id(expected.cp) != env.image.block_id(block),
+            ),
+            ("dp", expected.dp != env.dp),
+            ("cc", expected.cc != env.cc),
+            ("stack", expected.stack != env.stack),
+            ("output", expected.output != env.output),
+            ("state", expected.state != env.state),
+            ("step count", expected.steps != env.steps),
+        ];
+        match differences.iter().find(|(_, differs)| *differs) {
+            Some((what, _)) => Err(format!(
+                "IR {} differs after step {}: at {:?} dp {:?} cc {:?} stepping through colours, \
+                 at {:?} dp {:?} cc {:?} on the IR",
+                what, expected.steps, expected.cp, expected.dp, expected.cc, block, env.dp, env.cc,
+            )),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::asm::block_width;
+use crate::env::{get_op_color, PietOp};
+use crate::image::PietImg;
+use crate::ty::{Codel, DirectionPointer, PietColor};
+use druid::Data;
+use std::collections::HashMap;
+
+/// Colour every program starts with
+const START: PietColor = PietColor::Red;
+/// Colour of the block that ends a program
+const HALT: PietColor = PietColor::LightRed;
+
+/// How a straight run of commands is arranged in a program
+#[derive(Clone, Copy, Data, Debug, PartialEq)]
+pub enum Layout {
+    /// One row, left to right
+    Row,
+    /// Rows run left to right and right to left in turn, as wide as makes the program about
+    /// square
+    Snake,
+    /// Clockwise round the edge of a square and inwards, the square as small as fits
+    Spiral,
+}
+
+/// A straight part of the path, starting on the codel the last one turned on
+#[derive(Clone, Copy)]
+struct Leg {
+    dir: DirectionPointer,
+    /// Codels along it before the next leg's first
+    length: u32,
+    /// Value popped by the pointer that turns into the next leg
+    turn: u32,
+}
+
+/// Draws blocks along a path, each executing its command going into the next
+struct Turtle {
+    codels: HashMap<(i64, i64), PietColor>,
+    at: (i64, i64),
+    dir: DirectionPointer,
+    color: PietColor,
+    /// Rows of codels each block can take up, the path and those hanging off its right
+    height: u32,
+}
+
+fn step(dir: DirectionPointer) -> (i64, i64) {
+    match dir {
+        DirectionPointer::Right => (1, 0),
+        DirectionPointer::Down => (0, 1),
+        DirectionPointer::Left => (-1, 0),
+        DirectionPointer::Up => (0, -1),
+    }
+}
+
+impl Turtle {
+    /// Colour the codel `along` the path and `across` to the right of it
+    fn put(&mut self, along: u32, across: u32, color: PietColor) {
+        let (dx, dy) = step(self.dir);
+        let (rx, ry) = step(self.dir.clockwise());
+        let (along, across) = (along as i64, across as i64);
+        let (x, y) = self.at;
+        self.codels.insert(
+            (x + along * dx + across * rx, y + along * dy + across * ry),
+            color,
+        );
+    }
+
+    /// A block of `size` codels executing `op` when it's left, either all along the path or
+    /// with what doesn't fit in the first columns hanging off to the right. Leaving with the CC
+    /// to the left always goes from its last codel along the path. Returns how far along it
+    /// went.
+    fn block(&mut self, op: PietOp, size: u32, straight: bool) -> u32 {
+        let color = self.color;
+        let width = if straight {
+            size
+        } else {
+            block_width(size, self.height)
+        };
+        for along in 0..width {
+            self.put(along, 0, color);
+        }
+        for codel in 0..size - width {
+            let rows = self.height - 1;
+            self.put(codel / rows, 1 + codel % rows, color);
+        }
+        self.forward(width);
+        self.color = get_op_color(color, op);
+        width
+    }
+
+    fn forward(&mut self, codels: u32) {
+        let (dx, dy) = step(self.dir);
+        self.at.0 += dx * codels as i64;
+        self.at.1 += dy * codels as i64;
+    }
+
+    /// A last block, then white into a block all of whose ways out are blocked. It takes three
+    /// codels along the path and one to the right of the last two.
+    fn halt(&mut self) {
+        let color = self.color;
+        self.put(0, 0, color);
+        self.put(1, 0, PietColor::White);
+        self.put(2, 0, HALT);
+        self.put(2, 1, HALT);
+        self.put(1, 1, HALT);
+    }
+}
+
+/// Lay out `ops` along legs of a path, turning onto the next leg with a pointer at its end when
+/// the next command won't fit. Gives up if a leg can't even fit what it takes to turn.
+fn lay_along(
+    ops: &[(PietOp, u32)],
+    height: u32,
+    spacer: u32,
+    legs: impl Fn(usize) -> Leg,
+) -> Option<PietImg> {
+    let mut turtle = Turtle {
+        codels: HashMap::new(),
+        at: (0, 0),
+        dir: DirectionPointer::Right,
+        color: START,
+        height,
+    };
+    let mut k = 0;
+    let mut leg = legs(k);
+    let mut used = 0;
+    let mut turn = |turtle: &mut Turtle, leg: &mut Leg, used: &mut u32| {
+        // push and pointer leave the stack as it was, as do the push and pop of the spacer
+        // that keeps the new leg's blocks clear of the last one's. The pointer's block can be
+        // any size, so it reaches the corner.
+        turtle.block(PietOp::Push, leg.turn, true);
+        turtle.block(PietOp::Pointer, leg.length - *used - leg.turn, true);
+        k += 1;
+        *leg = legs(k);
+        turtle.dir = leg.dir;
+        *used = turtle.block(PietOp::Push, spacer, true) + turtle.block(PietOp::Pop, 1, true);
+        *used + leg.turn < leg.length
+    };
+    for (op, size) in ops.iter().copied() {
+        while used + block_width(size, height) + leg.turn + 1 > leg.length {
+            if !turn(&mut turtle, &mut leg, &mut used) {
+                return None;
+            }
+        }
+        used += turtle.block(op, size, false);
+    }
+    // the end needs as much room as turning, so it's never on a leg just for turning
+    while used + 3 + leg.turn + 1 > leg.length {
+        if !turn(&mut turtle, &mut leg, &mut used) {
+            return None;
+        }
+    }
+    turtle.halt();
+
+    let width = turtle.codels.keys().map(|(x, _)| *x).max().unwrap() + 1;
+    let height = turtle.codels.keys().map(|(_, y)| *y).max().unwrap() + 1;
+    let mut image = PietImg::filled(width as u32, height as u32, PietColor::Black);
+    for ((x, y), color) in turtle.codels {
+        image.set(Codel::new(x as u32, y as u32), color);
+    }
+    Some(image)
+}
+
+/// A program that runs `ops`, each with the size of the block it's executed from, then halts.
+///
+/// Blocks follow a path from the top left, taking up to `height` rows beside it. Where the path
+/// turns it runs `push 1` or `push 3` and `pointer`, then a push and pop to keep clear of the
+/// blocks before the corner, none of which changes the stack. Pointers and switches among
+/// `ops` only keep to the path if they don't turn anything.
+pub fn lay_out(ops: &[(PietOp, u32)], layout: Layout) -> PietImg {
+    let largest = ops.iter().map(|(_, size)| *size).max().unwrap_or(1);
+    let height = 2.max((largest as f64).sqrt().ceil() as u32);
+    let widest = block_width(largest, height);
+    let path: u32 = ops.iter().map(|(_, size)| block_width(*size, height)).sum();
+    match layout {
+        Layout::Row => lay_along(ops, height, 1, |_| Leg {
+            dir: DirectionPointer::Right,
+            length: u32::MAX,
+            turn: 1,
+        }),
+        Layout::Snake => {
+            // going down between rows has to leave room for both their blocks
+            let spacer = 1.max(2 * height - 3);
+            let between = spacer + 5;
+            let side = ((path * between) as f64).sqrt().ceil() as u32;
+            let width = side.max(spacer + widest + 8);
+            lay_along(ops, height, spacer, |k| {
+                let turn = if k % 4 < 2 { 1 } else { 3 };
+                match k % 4 {
+                    0 => Leg {
+                        dir: DirectionPointer::Right,
+                        length: width - 1,
+                        turn,
+                    },
+                    2 => Leg {
+                        dir: DirectionPointer::Left,
+                        length: width - 1,
+                        turn,
+                    },
+                    _ => Leg {
+                        dir: DirectionPointer::Down,
+                        length: spacer + turn + 2,
+                        turn,
+                    },
+                }
+            })
+        }
+        Layout::Spiral => {
+            // the spacer keeps a leg's blocks clear of the last one's, and rounds of the
+            // spiral are far enough apart for blocks and a gap between
+            let spacer = height;
+            let apart = height + 1;
+            let mut side = (((path + 8) * apart) as f64).sqrt().ceil() as u32;
+            side = side.max(spacer + widest + 8);
+            loop {
+                let legs = |k: usize| {
+                    let rounds = if k == 0 { 0 } else { (k as u32 - 1) / 2 };
+                    Leg {
+                        dir: [
+                            DirectionPointer::Right,
+                            DirectionPointer::Down,
+                            DirectionPointer::Left,
+                            DirectionPointer::Up,
+                        ][k % 4],
+                        length: (side - 1).saturating_sub(rounds * apart),
+                        turn: 1,
+                    }
+                };
+                if let Some(image) = lay_along(ops, height, spacer, legs) {
+                    break Some(image);
+                }
+                side += 1 + side / 8;
+            }
+        }
+    }
+    .expect("rows and snakes always fit")
+}
@@ -1,13 +1,41 @@
+mod asm;
+mod cfg;
+mod dialect;
+mod disasm;
+mod editor;
 mod env;
+mod generate;
+mod history;
 mod image;
+mod input;
+mod inspect;
+mod ir;
+mod layout;
+mod lint;
+mod looping;
 mod piet_widget;
+mod reach;
+mod runner;
+mod synth;
+mod transpile;
 mod ty;
+mod wat;
 
-use piet_widget::PietViewWidget;
+use piet_widget::{PietViewWidget, FIT_TO_WINDOW};
 
-use druid::widget::{Button, Flex, Label, RawLabel, Scroll, Slider, Split, TextBox};
-use druid::{AppLauncher, Color, Data, Env, Lens, LensExt, Size, Widget, WidgetExt, WindowDesc};
-use env::PietEnv;
+use dialect::{Dialect, White};
+use druid::im::HashSet;
+use druid::widget::{
+    Button, Checkbox, Either, Flex, Label, Radio, Scroll, SizedBox, Slider, Split, TextBox,
+};
+use druid::{AppLauncher, Color, Data, Env, Lens, LensExt, Widget, WidgetExt, WindowDesc};
+use editor::{FileDialogs, Tool};
+use env::{ExecState, PietEnv};
+use history::{History, Shortcuts};
+use input::SendOnEnter;
+use layout::Layout;
+use runner::Runner;
+use ty::{Codel, PietColor};
 use wasm_bindgen::prelude::*;
 
 mod macros {
@@ -17,6 +45,7 @@ mod macros {
             web_sys::console::log_1(&format!( $( $t )* ).into());
         }
     }
+    #[allow(unused_imports)]
     pub(crate) use log;
 }
 
@@ -31,36 +60,182 @@ pub fn wasm_main() {
 #[derive(Clone, Lens, Data)]
 struct AppData {
     env: PietEnv,
+    /// Clicks in the program view edit it rather than set breakpoints
     drawing: bool,
+    /// Colour painted by the editing tools
+    brush: PietColor,
+    tool: Tool,
+    /// Edits made to the program, for undo and redo
+    history: History,
+    /// Size the canvas will be given when resized, in codels
+    canvas_width: f64,
+    canvas_height: f64,
+    /// Whether the timer is stepping the program
+    running: bool,
+    /// Steps per second when not running at full speed
+    speed: f64,
+    /// Step as fast as we can without starving the UI
+    full_speed: bool,
+    /// Take every step on the IR too, stopping with an error where the two differ
+    check_ir: bool,
+    /// Stop with an error when the program comes back round to where it was without reading
+    detect_loops: bool,
+    /// Keep the codel pointer in view as the program runs
+    follow: bool,
+    /// Dim the parts of the program that can never run
+    dim_dead: bool,
+    /// The codel under the mouse in the program view
+    hover: Option<Codel>,
+    /// Every codel of the blocks the user wants execution to stop on
+    breakpoints: HashSet<Codel>,
+    /// The line of input being typed, not yet visible to the program
+    input_line: String,
+    /// Text the editor can generate a program to print
+    print_text: String,
+    /// How generated programs are arranged
+    layout: Layout,
+}
+
+impl AppData {
+    fn new(env: PietEnv) -> Self {
+        let (canvas_width, canvas_height) = (
+            env.image.png_info.width as f64,
+            env.image.png_info.height as f64,
+        );
+        AppData {
+            env,
+            drawing: false,
+            brush: PietColor::Red,
+            tool: Tool::Pencil,
+            history: History::default(),
+            canvas_width,
+            canvas_height,
+            running: false,
+            speed: 10.0,
+            full_speed: false,
+            check_ir: false,
+            detect_loops: false,
+            follow: false,
+            dim_dead: false,
+            hover: None,
+            breakpoints: HashSet::new(),
+            input_line: String::new(),
+            print_text: String::new(),
+            layout: Layout::Row,
+        }
+    }
 }
 
 fn build_root_widget() -> impl Widget<AppData> {
+    let mut dialects = Flex::row()
+        .with_child(Label::new("Dialect"))
+        .with_spacer(8.0);
+    for (name, dialect) in Dialect::PRESETS {
+        dialects.add_child(Radio::new(name, dialect));
+    }
     let visual = Flex::column()
-        .with_flex_child(
-            PietViewWidget {
-                cell_size: Size {
-                    width: 0.0,
-                    height: 0.0,
-                },
-            },
-            1.0,
-        )
+        .with_flex_child(PietViewWidget::new().controller(Shortcuts), 1.0)
         .with_child(
             Flex::column()
                 .with_child(
                     Flex::row()
+                        .with_flex_child(
+                            Button::new("Run")
+                                .on_click(|_ctx, data: &mut AppData, _: &Env| {
+                                    data.running = matches!(
+                                        data.env.state,
+                                        ExecState::Running | ExecState::WaitingForInput
+                                    );
+                                })
+                                .padding((5., 5.)),
+                            1.0,
+                        )
+                        .with_flex_child(
+                            Button::new("Pause")
+                                .on_click(|_ctx, data: &mut AppData, _: &Env| {
+                                    data.running = false;
+                                })
+                                .padding((5., 5.)),
+                            1.0,
+                        )
                         .with_flex_child(
                             Button::new("Step")
-                                .on_click(|ctx, env: &mut PietEnv, _: &Env| {
-                                    env.step();
+                                .on_click(|ctx, data: &mut AppData, _: &Env| {
+                                    data.step();
+                                    ctx.request_paint();
+                                })
+                                .padding((5., 5.)),
+                            1.0,
+                        )
+                        .with_flex_child(
+                            Button::new("Reset")
+                                .on_click(|ctx, data: &mut AppData, _: &Env| {
+                                    data.running = false;
+                                    data.env.reset();
                                     ctx.request_paint();
                                 })
-                                .lens(AppData::env)
                                 .padding((5., 5.)),
                             1.0,
                         )
                         .padding(8.0),
                 )
+                .with_child(
+                    Flex::row()
+                        .with_flex_child(
+                            Slider::new()
+                                .with_range(1.0, 60.0)
+                                .lens(AppData::speed)
+                                .expand_width(),
+                            1.0,
+                        )
+                        .with_child(
+                            Label::dynamic(|data: &AppData, _| {
+                                format!("{:.0} steps/s", data.speed)
+                            })
+                            .fix_width(80.0),
+                        )
+                        .with_child(Checkbox::new("Full speed").lens(AppData::full_speed))
+                        .with_spacer(8.0)
+                        .with_child(Checkbox::new("Check IR").lens(AppData::check_ir))
+                        .with_child(Label::dynamic(|data: &AppData, _| {
+                            // the IR slides across white, so it can't be checked otherwise
+                            if data.check_ir && data.env.dialect.white != White::Slide {
+                                " (not while stopping in white)".to_string()
+                            } else {
+                                String::new()
+                            }
+                        }))
+                        .with_spacer(8.0)
+                        .with_child(Checkbox::new("Stop on loops").lens(AppData::detect_loops))
+                        .with_spacer(8.0)
+                        .with_child(Checkbox::new("Follow").lens(AppData::follow))
+                        .with_spacer(8.0)
+                        .with_child(Checkbox::new("Dead code").lens(AppData::dim_dead))
+                        .with_spacer(8.0)
+                        .with_child(Button::new("Fit").on_click(
+                            |ctx, _data: &mut AppData, _: &Env| {
+                                ctx.submit_command(FIT_TO_WINDOW);
+                            },
+                        ))
+                        .with_spacer(8.0)
+                        .with_child(Checkbox::new("Edit").lens(AppData::drawing))
+                        .padding((8.0, 0.0, 8.0, 8.0)),
+                )
+                .with_child(
+                    dialects
+                        .lens(AppData::env.then(PietEnv::dialect))
+                        .padding((8.0, 0.0, 8.0, 8.0)),
+                )
+                .with_child(
+                    Label::dynamic(|state: &ExecState, _| format!("{}", state))
+                        .lens(AppData::env.then(PietEnv::state))
+                        .padding((8.0, 0.0, 8.0, 8.0)),
+                )
+                .with_child(Either::new(
+                    |data: &AppData, _| data.drawing,
+                    editor::build_editor(),
+                    SizedBox::empty(),
+                ))
                 .background(BACKGROUND),
         );
 
@@ -71,20 +246,20 @@ fn build_root_widget() -> impl Widget<AppData> {
                     Label::dynamic(|cc, _| format!("Codel Choser\n{}", cc))
                         .with_text_alignment(druid::TextAlignment::Center)
                         .lens(AppData::env.then(PietEnv::cc))
-                    .background(Color::grey8(24)),
+                        .background(Color::grey8(24)),
                     1.0,
                 )
                 .with_flex_child(
                     Label::dynamic(|dp, _| format!("Direction Pointer\n{}", dp))
                         .with_text_alignment(druid::TextAlignment::Center)
                         .lens(AppData::env.then(PietEnv::dp))
-                    .background(Color::grey8(28)),
+                        .background(Color::grey8(28)),
                     1.0,
                 )
                 .with_flex_child(
                     Label::dynamic(|frc, _| {
                         if *frc >= 8 {
-                            format!("Execution Terminated")
+                            "Execution Terminated".to_string()
                         } else {
                             format!("Flow Restriction Count\n{}", frc)
                         }
@@ -100,44 +275,77 @@ fn build_root_widget() -> impl Widget<AppData> {
             Scroll::new(
                 Label::dynamic(|data, _| format!("{}", data))
                     .lens(AppData::env.then(PietEnv::stack))
-                    .expand()
+                    .expand(),
             )
             .vertical()
             .background(Color::grey8(10)),
             1.0,
         )
+        .with_flex_child(
+            Scroll::new(
+                Label::dynamic(|data: &AppData, _| match data.hover {
+                    Some(codel) if data.env.image.contains(codel) => {
+                        inspect::describe(&data.env.image, codel)
+                    }
+                    _ => "Hover over a codel to inspect it".to_string(),
+                })
+                .expand(),
+            )
+            .vertical()
+            .background(Color::grey8(14)),
+            1.0,
+        )
         .with_flex_child(
             Scroll::new(
                 Label::dynamic(|data, _| format!("STDOUT\n\n{}", data))
                     .lens(AppData::env.then(PietEnv::output))
-                    .expand()
+                    .expand(),
             )
             .vertical()
             .background(Color::grey8(20)),
             1.0,
+        )
+        .with_flex_child(
+            Flex::column()
+                .with_flex_child(
+                    Scroll::new(
+                        Label::dynamic(|data, _| format!("STDIN\n\n{}", data))
+                            .lens(AppData::env.then(PietEnv::input))
+                            .expand(),
+                    )
+                    .vertical(),
+                    1.0,
+                )
+                .with_child(
+                    Flex::row()
+                        .with_flex_child(
+                            TextBox::new()
+                                .with_placeholder("Input")
+                                .lens(AppData::input_line)
+                                .controller(SendOnEnter)
+                                .expand_width(),
+                            1.0,
+                        )
+                        .with_child(
+                            Button::new("Send")
+                                .on_click(|_ctx, data: &mut AppData, _: &Env| data.send_input()),
+                        )
+                        .padding(5.0),
+                )
+                .background(Color::grey8(16)),
+            1.0,
         );
 
     Split::columns(visual, stack)
+        .controller(Runner::default())
+        .controller(FileDialogs)
 }
 
 pub fn main() {
-    let main_window = WindowDesc::new(|| build_root_widget());
+    let main_window = WindowDesc::new(build_root_widget);
 
-    let image = include_bytes!("../hello.png");
-    let decoder = png::Decoder::new(&image[..]);
-    let mut reader = decoder.read_info().unwrap();
-    let mut buf = vec![0; reader.output_buffer_size()];
-    let info = reader.next_frame(&mut buf).unwrap();
-    let bytes = &buf[..info.buffer_size()];
-
-    let image = image::PietImg::new(1, info, bytes);
-    let mut env = env::PietEnv::new(image);
-
-    // create the initial app state
-    let initial_state = AppData {
-        env,
-        drawing: false,
-    };
+    let image = image::PietImg::from_png(include_bytes!("../corpus/hello.png")).unwrap();
+    let initial_state = AppData::new(env::PietEnv::new(image, Dialect::SPEC));
 
     // start the application
     AppLauncher::with_window(main_window)
@@ -147,19 +355,33 @@ pub fn main() {
 
 #[cfg(test)]
 mod test {
-    use super::*;
+    use crate::asm::assemble;
+    use crate::cfg::Cfg;
+    use crate::dialect::{Dialect, Eof, InvalidChar, NegativeRoll, Rounding, Sign, White};
+    use crate::disasm::{disassemble, Outcome};
+    use crate::env::{get_op_color, ExecState, PietEnv, PietOp};
+    use crate::generate::{print_ops, print_program};
     use crate::image::PietImg;
+    use crate::ir::{Ir, IrEnv};
+    use crate::layout::{lay_out, Layout};
+    use crate::lint::{lint, lint_pixels, lint_png, to_json, to_text, Lint};
+    use crate::looping::{Loop, LoopDetector};
+    use crate::reach::{Reach, Role};
+    use crate::synth::{cost, Metric, Synth};
+    use crate::transpile::to_rust;
     use crate::ty::*;
-    use std::fs::File;
+    use crate::wat::{to_wat, HALTED, NOT_A_CHARACTER, OUT_OF_INPUT};
+    use crate::AppData;
+    use std::path::Path;
+
+    /// The Piet program in the PNG at `path`
+    fn load(path: impl AsRef<Path>) -> PietImg {
+        PietImg::from_png(&std::fs::read(path).unwrap()).unwrap()
+    }
 
     #[test]
     fn assert_color_decode_in_one_codel_golden_image() {
-        let decoder = png::Decoder::new(File::open("hello.png").unwrap());
-        let mut reader = decoder.read_info().unwrap();
-        let mut buf = vec![0; reader.output_buffer_size()];
-        let info = reader.next_frame(&mut buf).unwrap();
-        let bytes = &buf[..info.buffer_size()];
-        let image = PietImg::new(1, info, bytes);
+        let image = load("corpus/hello.png");
 
         assert_eq!(PietColor::from(&image[Codel::new(0, 0)]), PietColor::Red);
         assert_eq!(PietColor::from(&image[Codel::new(1, 0)]), PietColor::Red);
@@ -201,12 +423,7 @@ mod test {
 
     #[test]
     fn flood_fill_test_in_one_codel_golden_image() {
-        let decoder = png::Decoder::new(File::open("hello.png").unwrap());
-        let mut reader = decoder.read_info().unwrap();
-        let mut buf = vec![0; reader.output_buffer_size()];
-        let info = reader.next_frame(&mut buf).unwrap();
-        let bytes = &buf[..info.buffer_size()];
-        let image = PietImg::new(1, info, bytes);
+        let image = load("corpus/hello.png");
 
         // the three vertical magenta blocks on the top row
         let flood_fill = image.get_codels_in_block(Codel::new(19, 0));
@@ -220,4 +437,1701 @@ mod test {
         let flood_fill = image.get_codels_in_block(Codel::new(4, 6));
         assert_eq!(flood_fill.codels.len(), 1);
     }
+
+    #[test]
+    fn run_to_halt_in_one_codel_golden_image() {
+        let mut env = PietEnv::new(load("corpus/hello.png"), Dialect::SPEC);
+
+        while env.state == ExecState::Running {
+            env.step();
+        }
+        assert_eq!(env.state, ExecState::Halted);
+        assert_eq!(env.output, "Hello world!");
+
+        // stepping a halted program does nothing
+        let halted = env.clone();
+        env.step();
+        assert!(env == halted);
+    }
+
+    /// A program drawn a row of codels to a line, each a hue `r y g c b m` with `l` or `d` in
+    /// front for light or dark, `w` for white or `.` for black. Blank lines and leading
+    /// whitespace are skipped.
+    fn draw(art: &str) -> PietImg {
+        let rows: Vec<Vec<PietColor>> = art
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split_whitespace()
+                    .map(|name| match name {
+                        "w" => PietColor::White,
+                        "." => PietColor::Black,
+                        _ => {
+                            let (lightness, hue) = match name.split_at(name.len() - 1) {
+                                ("l", hue) => (0, hue),
+                                ("", hue) => (1, hue),
+                                ("d", hue) => (2, hue),
+                                _ => panic!("{} isn't a colour", name),
+                            };
+                            let hue = match "rygcbm".find(hue) {
+                                Some(hue) => hue as u32,
+                                None => panic!("{} isn't a colour", name),
+                            };
+                            PietColor::from_color_scale(hue, lightness)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let (width, height) = (rows[0].len() as u32, rows.len() as u32);
+        let mut image = PietImg::filled(width, height, PietColor::Black);
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(
+                row.len(),
+                width as usize,
+                "row {} isn't as wide as the first",
+                y
+            );
+            for (x, color) in row.iter().enumerate() {
+                image.set(Codel::new(x as u32, y as u32), *color);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn drawn_images_have_the_colours_drawn() {
+        let image = draw(
+            "
+            lr r  dr w
+            .  ly dm lm
+            ",
+        );
+        assert_eq!((image.png_info.width, image.png_info.height), (4, 2));
+        use PietColor::*;
+        let colors: Vec<PietColor> = (0..2)
+            .flat_map(|y| (0..4).map(move |x| Codel::new(x, y)))
+            .map(|codel| image.color(codel))
+            .collect();
+        assert_eq!(
+            colors,
+            [
+                LightRed,
+                Red,
+                DarkRed,
+                White,
+                Black,
+                LightYellow,
+                DarkMagenta,
+                LightMagenta
+            ]
+        );
+
+        // a concave block leaves right by the top of its disjoint edge with the CC to the left
+        let image = draw(
+            "
+            g g g
+            g . .
+            g g g
+            ",
+        );
+        let env = PietEnv::new(image, Dialect::SPEC);
+        assert_eq!(
+            env.get_block_transition(Codel::new(0, 0), DirectionPointer::Right, CodelChoser::Left),
+            (Codel::new(2, 0), 7)
+        );
+    }
+
+    #[test]
+    fn in_char_waits_for_input() {
+        let mut env = PietEnv::new(draw("r m lb"), Dialect::SPEC);
+
+        env.step();
+        assert_eq!(env.state, ExecState::WaitingForInput);
+        assert_eq!(env.cp, Codel::new(0, 0));
+        env.step();
+        assert_eq!(env.state, ExecState::WaitingForInput);
+
+        env.input.push_str("hi");
+        env.step();
+        assert_eq!(env.state, ExecState::Running);
+        assert_eq!(env.cp, Codel::new(1, 0));
+        env.step();
+        assert_eq!(env.output, "h");
+        assert_eq!(env.input, "i");
+    }
+
+    #[test]
+    fn in_number_reads_whitespace_delimited_integers() {
+        let mut env = PietEnv::new(draw("r lb"), Dialect::SPEC);
+        env.input.push_str("  -12 x 3\n");
+        env.step();
+        assert_eq!(*env.stack, vec![-12]);
+        assert_eq!(env.input, " x 3\n");
+    }
+
+    /// Leave red for the colour that runs `op`, with `stack` on the stack
+    fn step_op(op: PietOp, stack: &[i64]) -> PietEnv {
+        let (hue, lightness) = get_op_color(PietColor::Red, op).get_color_scale();
+        let to = format!(
+            "{}{}",
+            ["l", "", "d"][lightness as usize],
+            &"rygcbm"[hue as usize..][..1]
+        );
+        let mut env = PietEnv::new(draw(&format!("r {}", to)), Dialect::SPEC);
+        env.stack.extend_from_slice(stack);
+        env.step();
+        assert_eq!((env.cp, env.steps), (Codel::new(1, 0), 1), "{:?}", op);
+        env
+    }
+
+    #[test]
+    fn commands_change_the_stack_as_the_spec_says() {
+        use PietOp::*;
+        let cases: [(PietOp, &[i64], &[i64]); 27] = [
+            (Push, &[], &[1]),
+            (Pop, &[1, 2], &[1]),
+            (Pop, &[], &[]),
+            (Add, &[1, 2, 3], &[1, 5]),
+            (Add, &[1], &[1]),
+            (Subtract, &[7, 2], &[5]),
+            (Multiply, &[3, -4], &[-12]),
+            (Divide, &[7, 2], &[3]),
+            // rounded down, so it agrees with mod
+            (Divide, &[-7, 2], &[-4]),
+            (Divide, &[7, 0], &[7, 0]),
+            (Divide, &[i64::MIN, -1], &[i64::MIN, -1]),
+            // the sign of the divisor
+            (Mod, &[7, -3], &[-2]),
+            (Mod, &[-7, 3], &[2]),
+            (Mod, &[7, 0], &[7, 0]),
+            (Not, &[0], &[1]),
+            (Not, &[5], &[0]),
+            (Greater, &[3, 5], &[0]),
+            (Greater, &[5, 3], &[1]),
+            (Duplicate, &[4], &[4, 4]),
+            (Duplicate, &[], &[]),
+            (Roll, &[1, 2, 3, 4, 3, 1], &[1, 4, 2, 3]),
+            (Roll, &[1, 2, 3, 3, -1], &[2, 3, 1]),
+            (Roll, &[1, 2, 0, 5], &[1, 2]),
+            // a negative depth, or one deeper than the stack, is ignored
+            (Roll, &[1, 2, -1, 1], &[1, 2, -1, 1]),
+            (Roll, &[1, 5, 1], &[1, 5, 1]),
+            (OutNumber, &[1, -42], &[1]),
+            (OutNumber, &[], &[]),
+        ];
+        for (op, before, after) in cases {
+            let env = step_op(op, before);
+            assert_eq!(*env.stack, after, "{:?} on {:?}", op, before);
+        }
+        assert_eq!(step_op(OutNumber, &[-42]).output, "-42");
+    }
+
+    #[test]
+    fn pointer_and_switch_turn_by_what_they_pop() {
+        use PietOp::*;
+        let turned = |op, val| {
+            let env = step_op(op, &[val]);
+            assert!(env.stack.is_empty());
+            (env.dp, env.cc)
+        };
+        use CodelChoser::{Left, Right};
+        use DirectionPointer::{Down, Up};
+        assert_eq!(turned(Pointer, 1), (Down, Left));
+        assert_eq!(turned(Pointer, -1), (Up, Left));
+        assert_eq!(turned(Pointer, 4), (DirectionPointer::Right, Left));
+        assert_eq!(turned(Pointer, 6), (DirectionPointer::Left, Left));
+        assert_eq!(turned(Switch, 1), (DirectionPointer::Right, Right));
+        assert_eq!(turned(Switch, -1), (DirectionPointer::Right, Right));
+        assert_eq!(turned(Switch, 2), (DirectionPointer::Right, Left));
+    }
+
+    #[test]
+    fn white_is_slid_across_without_a_command() {
+        // right across white, turned down by black into green
+        let mut env = PietEnv::new(
+            draw(
+                "
+                r w w .
+                . . w .
+                . . g .
+                ",
+            ),
+            Dialect::SPEC,
+        );
+        env.stack.extend_from_slice(&[7, 2]);
+        env.step();
+        assert_eq!(env.cp, Codel::new(2, 2));
+        assert_eq!(
+            (env.dp, env.cc),
+            (DirectionPointer::Down, CodelChoser::Right)
+        );
+        assert_eq!(*env.stack, vec![7, 2]);
+        assert_eq!((env.steps, env.state), (1, ExecState::Running));
+
+        // white going round in a circle can never be got out of
+        let mut env = PietEnv::new(
+            draw(
+                "
+                r w w .
+                . w w .
+                . . . .
+                ",
+            ),
+            Dialect::SPEC,
+        );
+        env.step();
+        assert_eq!((env.cp, env.state), (Codel::new(0, 0), ExecState::Halted));
+    }
+
+    #[test]
+    fn rerun_replays_steps_and_input_on_edited_image() {
+        let mut env = PietEnv::new(draw("r m lb"), Dialect::SPEC);
+        env.input.push_str("hi");
+        env.step();
+        env.step();
+        assert_eq!(env.output, "h");
+        assert_eq!(env.steps, 2);
+
+        // InChar then Duplicate instead of OutChar
+        let mut image = env.image.clone();
+        image.set(Codel::new(2, 0), PietColor::Cyan);
+        assert_eq!(image.block(Codel::new(2, 0)).codels, vec![Codel::new(2, 0)]);
+        env.rerun(image);
+        assert_eq!(env.steps, 2);
+        assert_eq!(env.output, "");
+        assert_eq!(*env.stack, vec!['h' as i64, 'h' as i64]);
+        assert_eq!(env.input, "i");
+        assert_eq!(env.input_read, "h");
+    }
+
+    #[test]
+    fn next_transition_predicts_step_in_one_codel_golden_image() {
+        let mut env = PietEnv::new(load("corpus/hello.png"), Dialect::SPEC);
+
+        while env.state == ExecState::Running {
+            let transition = env.next_transition();
+            let (cp, dp, cc) = (env.cp, env.dp, env.cc);
+            env.step();
+            match transition.entry {
+                Some(entry) => assert_eq!(env.cp, entry),
+                None => {
+                    assert_eq!(env.cp, cp);
+                    assert!(env.dp != dp || env.cc != cc);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn describe_block_in_one_codel_golden_image() {
+        let image = load("corpus/hello.png");
+
+        let description = crate::inspect::describe(&image, Codel::new(19, 0));
+        assert!(description.contains("DarkMagenta, hue 5 (magenta) lightness 2 (dark)"));
+        assert!(description.contains("Block of 3 codels, (19, 0) to (19, 2)"));
+        assert!(description.contains("Blue at (20, 0): OutChar"));
+        assert!(description.contains("Magenta at (18, 0): Pop"));
+    }
+
+    #[test]
+    fn edit_and_round_trip_through_png() {
+        use crate::editor::Tool;
+        use PietColor::*;
+        let image = draw(
+            "
+            r r w
+            w w w
+            ",
+        );
+        let mut data = AppData::new(PietEnv::new(image, Dialect::SPEC));
+        data.tool = Tool::Fill;
+        data.brush = Blue;
+        data.apply_tool(Codel::new(1, 0));
+        let image = data.env.image.clone();
+        assert_eq!(image.color(Codel::new(0, 0)), Blue);
+        assert_eq!(image.color(Codel::new(2, 0)), White);
+
+        let image = image.resized(4, 1);
+        assert_eq!(image.png_info.width, 4);
+        assert_eq!(image.png_info.height, 1);
+        assert_eq!(image.color(Codel::new(1, 0)), Blue);
+        assert_eq!(image.color(Codel::new(3, 0)), White);
+
+        let png = image.to_png().unwrap();
+        assert!(PietImg::from_png(&png).unwrap() == image);
+    }
+
+    #[test]
+    fn op_color_inverts_get_op() {
+        use crate::env::{get_op, get_op_color, OPS};
+        for hue in 0..6 {
+            for lightness in 0..3 {
+                let from = PietColor::from_color_scale(hue, lightness);
+                assert_eq!(from.get_color_scale(), (hue, lightness));
+                for op in OPS.iter().flatten() {
+                    assert_eq!(get_op(from, get_op_color(from, *op)), *op);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn undo_redo_groups_strokes() {
+        use crate::history::{Edit, History};
+        use PietColor::*;
+        let original = PietImg::filled(3, 1, White);
+        let mut image = original.clone();
+        let mut history = History::default();
+
+        let paint = |history: &mut History, image: &mut PietImg, x, color| {
+            let edit = Edit::Paint(vec![(
+                Codel::new(x, 0),
+                image.color(Codel::new(x, 0)),
+                color,
+            )]);
+            edit.apply(image);
+            history.record(edit);
+        };
+
+        history.begin_group();
+        paint(&mut history, &mut image, 0, Red);
+        paint(&mut history, &mut image, 1, Red);
+        paint(&mut history, &mut image, 0, Blue);
+        history.end_group();
+        paint(&mut history, &mut image, 2, Green);
+        let painted = image.clone();
+
+        // the last paint was outside the stroke so comes off on its own
+        let image = history.undo(&image).unwrap();
+        assert_eq!(image.color(Codel::new(2, 0)), White);
+        assert_eq!(image.color(Codel::new(0, 0)), Blue);
+        let image = history.undo(&image).unwrap();
+        assert!(image == original);
+        assert!(history.undo(&image).is_none());
+
+        let image = history.redo(&image).unwrap();
+        let image = history.redo(&image).unwrap();
+        assert!(image == painted);
+        assert!(history.redo(&image).is_none());
+
+        let resized = image.resized(5, 5);
+        history.record(Edit::Replace {
+            before: image.clone(),
+            after: resized.clone(),
+        });
+        assert!(history.undo(&resized).unwrap() == painted);
+    }
+
+    #[test]
+    fn strokes_rerun_the_program_once_they_end() {
+        let mut data = AppData::new(PietEnv::new(draw("r y g c b m"), Dialect::SPEC));
+        for _ in 0..3 {
+            data.env.step();
+        }
+        data.brush = PietColor::Black;
+
+        // walling off red leaves the program where it was until the stroke's over
+        data.begin_stroke();
+        data.apply_tool(Codel::new(1, 0));
+        data.apply_tool(Codel::new(2, 0));
+        assert_eq!(data.env.image.color(Codel::new(2, 0)), PietColor::Black);
+        assert_eq!((data.env.cp, data.env.steps), (Codel::new(3, 0), 3));
+        data.end_stroke();
+        assert_eq!((data.env.cp, data.env.steps), (Codel::new(0, 0), 3));
+
+        // and the stroke comes off in one go
+        data.undo();
+        assert!(data.env.image == draw("r y g c b m"));
+        assert_eq!(data.env.cp, Codel::new(3, 0));
+        assert!(data.history.undo(&data.env.image).is_none());
+    }
+
+    #[test]
+    fn hover_is_dropped_when_the_program_changes() {
+        let mut data = AppData::new(PietEnv::new(draw("r y g c b m"), Dialect::SPEC));
+        data.hover = Some(Codel::new(5, 0));
+        data.canvas_width = 2.0;
+        data.resize_canvas();
+        assert_eq!(data.hover, None);
+        data.hover = Some(Codel::new(1, 0));
+        data.undo();
+        assert_eq!(data.hover, None);
+    }
+
+    fn run(image: PietImg, input: &str) -> PietEnv {
+        let mut env = PietEnv::new(image, Dialect::SPEC);
+        env.input.push_str(input);
+        while env.state == ExecState::Running && env.steps < 100_000 {
+            env.step();
+        }
+        env
+    }
+
+    #[test]
+    fn assembled_straight_line_program_runs() {
+        let source = "
+            push 72   ; H
+            outchar
+            push 7
+            push 15
+            mul
+            outchar   ; i
+        ";
+        let env = run(assemble(source).unwrap(), "");
+        assert_eq!(env.state, ExecState::Halted);
+        assert_eq!(env.output, "Hi");
+        assert!(env.stack.is_empty());
+    }
+
+    #[test]
+    fn assembled_loop_counts_down() {
+        let source = "
+            innum
+        loop:
+            dup
+            outnum
+            push 1
+            sub
+            dup
+            jnz loop
+            pop
+            jmp end
+            push 9   # never run
+            outnum
+        end:
+            push 1
+            jz loop
+            push 2
+            outnum
+        ";
+        let env = run(assemble(source).unwrap(), "5");
+        assert_eq!(env.state, ExecState::Halted);
+        assert_eq!(env.output, "543212");
+    }
+
+    #[test]
+    fn assembled_arithmetic_follows_the_spec() {
+        let source = "
+            push 1
+            push 8
+            sub
+            dup
+            push 2
+            div
+            outnum     ; -7 / 2 rounds down to -4
+            push 3
+            mod
+            outnum     ; -7 mod 3 takes the sign of 3
+            push 1
+            push 2
+            push 3
+            push 3
+            push 1
+            roll       ; buries the 3 under the 1 and 2
+            outnum
+            outnum
+            outnum
+            push 5
+            push 3
+            gt
+            not
+            outnum
+        ";
+        let env = run(assemble(source).unwrap(), "");
+        assert_eq!(env.output, "-422130");
+    }
+
+    #[test]
+    fn assembler_reports_the_line_of_an_error() {
+        let error = assemble("push 1\n\njmp nowhere\n").err().unwrap();
+        assert_eq!(error.line, 3);
+        assert_eq!(assemble("push 0").err().unwrap().line, 1);
+        assert_eq!(assemble("a:\na:").err().unwrap().line, 2);
+        assert_eq!(assemble("frobnicate").err().unwrap().line, 1);
+    }
+
+    #[test]
+    fn disassembly_lists_every_exit_of_reachable_blocks() {
+        // the white codel bounces back into the blue block, which never gets out again
+        let image = draw(
+            "
+            r r m w
+            . . . b
+            ",
+        );
+        let listing = disassemble(&image, &Dialect::SPEC).to_string();
+        assert_eq!(
+            listing,
+            "block 0 at (0, 0): Red, 2 codels
+    dp right cc left: inchar -> block 1 at (2, 0)
+block 1 at (2, 0): Magenta, 1 codel
+    dp right cc left: slide -> block 2 at (3, 1), now dp down cc right
+block 2 at (3, 1): Blue, 1 codel
+    dp down cc left: blocked
+    dp down cc right: blocked
+    dp left cc left: blocked
+    dp left cc right: blocked
+    dp up cc left: slide -> block 2 at (3, 1), now dp down cc left
+    dp up cc right: slide -> block 2 at (3, 1), now dp down cc right
+",
+        );
+    }
+
+    #[test]
+    fn disassembly_covers_every_block_a_run_visits() {
+        let image = load("corpus/hello.png");
+        let disassembly = disassemble(&image, &Dialect::SPEC);
+
+        let mut env = PietEnv::new(image.clone(), Dialect::SPEC);
+        while env.state == ExecState::Running {
+            let (cp, dp, cc) = (env.cp, env.dp, env.cc);
+            env.step();
+            let block = disassembly
+                .blocks
+                .iter()
+                .find(|block| image.block_id(block.codel) == image.block_id(cp))
+                .unwrap();
+            let (_, _, outcome) = block
+                .exits
+                .iter()
+                .find(|(d, c, _)| (*d, *c) == (dp, cc))
+                .unwrap();
+            if let Outcome::Move { target, .. } = outcome {
+                let target = disassembly.blocks[*target].codel;
+                assert_eq!(image.block_id(target), image.block_id(env.cp));
+            }
+        }
+    }
+
+    #[test]
+    fn control_flow_graph_branches_on_pointers() {
+        let image = assemble("innum\njz end\npush 1\nend:\npush 2\noutnum").unwrap();
+        let disassembly = disassemble(&image, &Dialect::SPEC);
+        let cfg = Cfg::new(&disassembly);
+
+        // every state has somewhere to go
+        for id in 0..cfg.states.len() {
+            assert!(cfg.edges.iter().any(|edge| edge.from == id) || cfg.trapped.contains(&id));
+        }
+        // a pointer leaves in all four directions
+        let pointers: Vec<_> = cfg
+            .edges
+            .iter()
+            .filter(|edge| edge.command == Some(PietOp::Pointer))
+            .collect();
+        assert!(!pointers.is_empty());
+        for from in pointers.iter().map(|edge| edge.from) {
+            let directions: std::collections::HashSet<_> = pointers
+                .iter()
+                .filter(|edge| edge.from == from)
+                .map(|edge| cfg.states[edge.to].dp)
+                .collect();
+            assert_eq!(directions.len(), 4);
+        }
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph program {"));
+        assert_eq!(dot.matches(" -> ").count(), cfg.edges.len());
+        assert_eq!(dot.matches("label=\"pointer ").count(), pointers.len());
+
+        let json = cfg.to_json();
+        assert_eq!(json.matches("\"from\"").count(), cfg.edges.len());
+        assert_eq!(json.matches("\"dp\"").count(), cfg.states.len());
+        assert_eq!(json.matches("\"size\"").count(), disassembly.blocks.len());
+        assert!(json.contains("\"command\": \"pointer\", \"branch\": 3"));
+    }
+
+    #[test]
+    fn generated_programs_print_their_text() {
+        for layout in [Layout::Row, Layout::Snake, Layout::Spiral] {
+            for text in ["", "a", "Hello, world!\n", "zzz  AAA", "\0\u{1F600}"] {
+                let env = run(print_program(text, layout), "");
+                assert_eq!(env.state, ExecState::Halted);
+                assert_eq!(env.output, text);
+                assert!(env.stack.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn generated_programs_build_characters_cheaply() {
+        // 'H' is made from 72 = 8 * 9 or similar, far smaller than a block of 72
+        let h = print_ops("H");
+        assert!(h.contains(&(PietOp::Multiply, 1)) && !h.contains(&(PietOp::Push, 72)));
+        // 'i' follows 'h' by adding one
+        let added = [
+            (PietOp::Duplicate, 1),
+            (PietOp::OutChar, 1),
+            (PietOp::Push, 1),
+            (PietOp::Add, 1),
+        ];
+        assert!(print_ops("hi").windows(4).any(|ops| ops == added));
+    }
+
+    /// Every command executed running `image`, with the size of the block it's executed from
+    fn trace(image: PietImg, input: &str) -> Vec<(PietOp, u32)> {
+        let mut env = PietEnv::new(image, Dialect::SPEC);
+        env.input = input.to_string();
+        let mut ops = vec![];
+        while env.state == ExecState::Running && env.steps < 100_000 {
+            let transition = env.next_transition();
+            let from = env.cp;
+            env.step();
+            match transition.op {
+                Some(PietOp::None) | None => {}
+                Some(op) if env.cp != from => ops.push((op, transition.block_size)),
+                Some(_) => {}
+            }
+        }
+        ops
+    }
+
+    #[test]
+    fn layouts_run_their_commands_in_order() {
+        use PietOp::*;
+        let mut ops = vec![
+            (Push, 30),
+            (InNumber, 1),
+            (Duplicate, 1),
+            (Push, 7),
+            (Roll, 1),
+        ];
+        for i in 1..60 {
+            ops.extend([(Push, i % 11 + 1), (Multiply, 1), (Push, 3), (Mod, 1)]);
+            ops.extend([(Duplicate, 1), (OutNumber, 1), (Push, 2), (Add, 1)]);
+        }
+        ops.extend([
+            (Greater, 1),
+            (OutNumber, 1),
+            (Push, 1),
+            (Not, 1),
+            (OutNumber, 1),
+        ]);
+        let expected = run(lay_out(&ops, Layout::Row), "5");
+        for layout in [Layout::Row, Layout::Snake, Layout::Spiral] {
+            let image = lay_out(&ops, layout);
+            let env = run(image.clone(), "5");
+            assert_eq!(env.state, ExecState::Halted, "{:?}", layout);
+            assert_eq!(env.output, expected.output, "{:?}", layout);
+            assert_eq!(env.stack, expected.stack, "{:?}", layout);
+
+            // everything but the ops is a turn or a spacer, neither changing the stack
+            let traced = trace(image, "5");
+            let mut rest = &traced[..];
+            for op in ops.iter().map(Some).chain([Option::None]) {
+                while rest.first() != op {
+                    assert!(
+                        matches!(rest[..2], [(Push, _), (Pointer, _)] | [(Push, _), (Pop, 1)]),
+                        "{:?}: {:?}",
+                        layout,
+                        &rest[..2]
+                    );
+                    rest = &rest[2..];
+                }
+                rest = rest.get(1..).unwrap_or_default();
+            }
+        }
+        let (snake, spiral) = (lay_out(&ops, Layout::Snake), lay_out(&ops, Layout::Spiral));
+        for image in [snake, spiral] {
+            let (width, height) = (image.png_info.width, image.png_info.height);
+            assert!(
+                width < 2 * height && height < 2 * width,
+                "{}x{}",
+                width,
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn synthesised_constants_push_their_value() {
+        let values = [
+            0,
+            1,
+            7,
+            72,
+            127,
+            255,
+            256,
+            997,
+            65_536,
+            1_000_003,
+            -1,
+            -300,
+            i64::MAX,
+            i64::MIN,
+        ];
+        let source: String = values
+            .iter()
+            .map(|n| format!("num {}\noutnum\n", n))
+            .collect();
+        let env = run(assemble(&source).unwrap(), "");
+        let expected: String = values.iter().map(|n| n.to_string()).collect();
+        assert_eq!(env.output, expected);
+        assert!(env.stack.is_empty());
+
+        let mut synth = Synth::new(Metric::Commands(5));
+        for n in [1, 6, 100, 4_096, 12_345] {
+            assert!(synth.plan(n).iter().all(|(_, size)| *size <= 5));
+            let env = run(assemble(&format!("num {} 5\noutnum", n)).unwrap(), "");
+            assert_eq!(env.output, n.to_string());
+        }
+    }
+
+    /// The least any sequence of push, dup, add, subtract, multiply and roll can leave each
+    /// value from 1 to `max` on its own on the stack with in `metric`, by trying every stack of
+    /// up to four values within `bound` cheapest first
+    fn brute_force(metric: Metric, max: i64, bound: i64) -> Vec<u64> {
+        use std::cmp::Reverse;
+        use std::collections::{BinaryHeap, HashMap};
+        let mut best: HashMap<Vec<i64>, u64> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let mut found = vec![u64::MAX; max as usize + 1];
+        let mut remaining = max;
+        queue.push(Reverse((0, vec![])));
+        while let Some(Reverse((cost, stack))) = queue.pop() {
+            if best.get(&stack).is_some_and(|best| *best < cost) {
+                continue;
+            }
+            if let [n] = stack[..] {
+                if (1..=max).contains(&n) && found[n as usize] == u64::MAX {
+                    found[n as usize] = cost;
+                    remaining -= 1;
+                    if remaining == 0 {
+                        return found;
+                    }
+                }
+            }
+            let mut next = vec![];
+            let (largest, area) = match metric {
+                Metric::Area => (max, true),
+                Metric::Commands(largest) => (largest as i64, false),
+            };
+            for size in 1..=bound.min(largest) {
+                let mut pushed = stack.clone();
+                pushed.push(size);
+                next.push((cost + if area { size as u64 } else { 1 }, pushed));
+            }
+            let len = stack.len();
+            if let Some(top) = stack.last() {
+                let mut dup = stack.clone();
+                dup.push(*top);
+                next.push((cost + 1, dup));
+            }
+            if len >= 2 {
+                let (a, b) = (stack[len - 2], stack[len - 1]);
+                for val in [a + b, a - b, a * b] {
+                    let mut done = stack[..len - 2].to_vec();
+                    done.push(val);
+                    next.push((cost + 1, done));
+                }
+                if b >= 0 && a >= 0 && (a as usize) <= len - 2 {
+                    let mut rolled = stack[..len - 2].to_vec();
+                    if a > 0 {
+                        let start = rolled.len() - a as usize;
+                        rolled[start..].rotate_right(b.rem_euclid(a) as usize);
+                    }
+                    next.push((cost + 1, rolled));
+                }
+            }
+            for (cost, stack) in next {
+                if stack.len() > 4 || stack.iter().any(|v| v.abs() > bound) {
+                    continue;
+                }
+                if best.get(&stack).is_none_or(|best| cost < *best) {
+                    best.insert(stack.clone(), cost);
+                    queue.push(Reverse((cost, stack)));
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn synthesised_constants_are_as_small_as_brute_force() {
+        for (metric, max) in [
+            (Metric::Area, 30),
+            (Metric::Commands(1), 40),
+            (Metric::Commands(5), 60),
+        ] {
+            let brute = brute_force(metric, max, 128);
+            let mut synth = Synth::new(metric);
+            for n in 1..=max {
+                let plan = synth.plan(n);
+                assert_eq!(
+                    cost(metric, &plan),
+                    brute[n as usize],
+                    "{:?} {}: {:?}",
+                    metric,
+                    n,
+                    plan
+                );
+            }
+        }
+    }
+
+    /// What `image` prints compiled to Rust, given `input`
+    fn run_compiled(image: &PietImg, input: &str, name: &str) -> String {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+        let dir = std::env::temp_dir().join(format!("cornelis-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = to_rust(&Cfg::new(&disassemble(image, &Dialect::SPEC))).unwrap();
+        std::fs::write(dir.join("main.rs"), source).unwrap();
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let compiled = Command::new(rustc)
+            .args(["--edition", "2021", "main.rs", "-o", "main"])
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(compiled.success(), "{} didn't compile", name);
+        let mut child = Command::new(dir.join("main"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn compiled_programs_print_what_they_print_interpreted() {
+        let hello = load("corpus/hello.png");
+
+        let echo = assemble(
+            "
+            innum
+            inchar
+            pop
+        loop:
+            inchar
+            dup
+            outchar
+            push 10
+            sub
+            jnz loop
+            outnum
+            push 3
+            push 1
+            roll
+            push 1
+            push 2
+            sub
+            dup
+            push 3
+            mod
+            outnum
+            push 2
+            div
+            outnum
+            num -1
+            outchar    ; not a character
+            push 1
+            outnum
+            ",
+        )
+        .unwrap();
+        let programs = [
+            ("hello", hello, ""),
+            ("echo", echo, "-12 ünïcode\n"),
+            (
+                "echo_short",
+                assemble("innum\ninchar\noutchar").unwrap(),
+                "7",
+            ),
+            (
+                "spiral",
+                print_program("Hello, world!\n", Layout::Spiral),
+                "",
+            ),
+        ];
+        for (name, image, input) in programs {
+            let env = run(image.clone(), input);
+            assert_ne!(env.state, ExecState::Running);
+            assert_eq!(run_compiled(&image, input, name), env.output, "{}", name);
+        }
+    }
+
+    /// What `image` gives back and prints compiled to WebAssembly, given `input`
+    fn run_wat(image: &PietImg, input: &str) -> (i32, String) {
+        use wasmi::{Caller, Engine, Linker, Module, Store};
+        struct Io {
+            input: String,
+            read: usize,
+            output: String,
+        }
+        let wasm = wat::parse_str(to_wat(&Cfg::new(&disassemble(image, &Dialect::SPEC))).unwrap())
+            .unwrap();
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm[..]).unwrap();
+        let mut store = Store::new(
+            &engine,
+            Io {
+                input: input.to_string(),
+                read: 0,
+                output: String::new(),
+            },
+        );
+        let mut linker = <Linker<Io>>::new(&engine);
+        linker
+            .func_wrap("io", "out_number", |mut caller: Caller<Io>, val: i64| {
+                caller.data_mut().output.push_str(&val.to_string())
+            })
+            .unwrap();
+        linker
+            .func_wrap("io", "out_char", |mut caller: Caller<Io>, c: i32| {
+                let c = char::from_u32(c as u32).unwrap();
+                caller.data_mut().output.push(c)
+            })
+            .unwrap();
+        linker
+            .func_wrap("io", "in_char", |mut caller: Caller<Io>| -> i64 {
+                let io = caller.data_mut();
+                match io.input[io.read..].chars().next() {
+                    Some(c) => {
+                        io.read += c.len_utf8();
+                        c as i64
+                    }
+                    None => -1,
+                }
+            })
+            .unwrap();
+        linker
+            .func_wrap("io", "in_number", |mut caller: Caller<Io>| -> (i32, i64) {
+                let io = caller.data_mut();
+                let rest = &io.input[io.read..];
+                let start = rest.len() - rest.trim_start().len();
+                if start == rest.len() {
+                    io.read = io.input.len();
+                    return (0, 0);
+                }
+                let end = rest[start..]
+                    .find(char::is_whitespace)
+                    .map_or(rest.len(), |end| start + end);
+                let parsed = rest[start..end].parse();
+                io.read += end;
+                match parsed {
+                    Ok(val) => (1, val),
+                    Err(_) => (2, 0),
+                }
+            })
+            .unwrap();
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let run = instance.get_typed_func::<(), i32>(&store, "run").unwrap();
+        let status = run.call(&mut store, ()).unwrap();
+        (status, store.into_data().output)
+    }
+
+    #[test]
+    fn wasm_programs_print_what_they_print_interpreted() {
+        let hello = load("corpus/hello.png");
+
+        let arithmetic = assemble(
+            "
+            innum
+            dup
+            push 3
+            mod
+            outnum
+            dup
+            push 3
+            div
+            outnum
+            push 3
+            push 2
+            sub
+            dup
+            push 3
+            push 1
+            roll
+            div
+            outnum
+            push 5
+            push 4
+            gt
+            not
+            outnum
+            push 1
+            push 2
+            push 3
+            push 4
+            push 3
+            push 2
+            sub
+            push 2
+            sub
+            roll
+            outnum
+            outnum
+            outnum
+            outnum
+            inchar
+            pop
+        loop:
+            inchar
+            dup
+            outchar
+            push 10
+            sub
+            jnz loop
+            num -1
+            outchar    ; not a character
+            ",
+        )
+        .unwrap();
+        let programs = [
+            (hello, "", HALTED),
+            (arithmetic, "-7 ünïcode\n", NOT_A_CHARACTER),
+            (
+                assemble("innum\ninchar\noutchar").unwrap(),
+                "7",
+                OUT_OF_INPUT,
+            ),
+            (assemble("innum\ninnum\noutnum").unwrap(), "x 5", HALTED),
+            (print_program("Hello, world!\n", Layout::Snake), "", HALTED),
+        ];
+        for (image, input, status) in programs {
+            let env = run(image.clone(), input);
+            assert_eq!(run_wat(&image, input), (status, env.output.clone()));
+            match status {
+                HALTED => assert_eq!(env.state, ExecState::Halted),
+                OUT_OF_INPUT => assert_eq!(env.state, ExecState::WaitingForInput),
+                _ => assert!(matches!(env.state, ExecState::Error(_))),
+            }
+        }
+    }
+
+    /// Numbers below the `n` each call is given, the same ones every run for the same `seed`
+    fn random(mut seed: u64) -> impl FnMut(u32) -> u32 {
+        move |n| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as u32
+        }
+    }
+
+    /// Run `image` with `input` both through its colours and on its IR, step for step, failing on
+    /// the first difference
+    fn cross_check(image: &PietImg, input: &str, max_steps: usize) -> Result<PietEnv, String> {
+        let ir = Ir::new(&Cfg::new(&disassemble(image, &Dialect::SPEC)));
+        let mut expected = PietEnv::new(image.clone(), Dialect::SPEC);
+        expected.input.push_str(input);
+        let mut actual = IrEnv::resume(&ir, expected.clone()).unwrap();
+        while expected.state == ExecState::Running && expected.steps < max_steps {
+            expected.step();
+            actual.step();
+            actual.compare(&expected)?;
+        }
+        Ok(expected)
+    }
+
+    #[test]
+    fn ir_steps_like_the_colours_it_was_built_from() {
+        let hello = load("corpus/hello.png");
+        let env = cross_check(&hello, "", 100_000).unwrap();
+        assert_eq!(env.state, ExecState::Halted);
+
+        let echo = assemble("innum\ninchar\noutchar\ninnum\noutnum").unwrap();
+        let env = cross_check(&echo, "7", 100_000).unwrap();
+        assert_eq!(env.state, ExecState::WaitingForInput);
+        for layout in [Layout::Row, Layout::Snake, Layout::Spiral] {
+            let image = print_program("Fused!\n", layout);
+            assert_eq!(cross_check(&image, "", 100_000).unwrap().output, "Fused!\n");
+        }
+
+        // random programs, most of which loop, turn and get restricted all over the place
+        let mut random = random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..300 {
+            let (width, height) = (2 + random(7), 2 + random(7));
+            let mut image = PietImg::filled(width, height, PietColor::Black);
+            for y in 0..height {
+                for x in 0..width {
+                    let color = match random(24) {
+                        0..=2 => PietColor::Black,
+                        3..=5 => PietColor::White,
+                        _ => PietColor::from_color_scale(random(6), random(3)),
+                    };
+                    image.set(Codel::new(x, y), color);
+                }
+            }
+            image.set(Codel::new(0, 0), PietColor::Red);
+            if let Err(difference) = cross_check(&image, "3 -2 x 17 hello", 2_000) {
+                panic!("{}", difference);
+            }
+        }
+    }
+
+    #[test]
+    fn reach_finds_dead_and_turned_into_code() {
+        // red runs a pointer into dark cyan, which only goes down into green if it turns. Blue
+        // sends it back round, so red is never left downwards. Yellow is walled off and white is
+        // never slid into.
+        let image = draw(
+            "
+            r r dc b .
+            . . g  . .
+            w . .  . y
+            ",
+        );
+        let reach = Reach::new(&image, &Dialect::SPEC);
+        let roles: Vec<Vec<Role>> = (0..3)
+            .map(|y| (0..5).map(|x| reach.role(Codel::new(x, y))).collect())
+            .collect();
+        use Role::*;
+        assert_eq!(
+            roles,
+            [
+                [Live, Live, Live, Live, Live],
+                [Decorative, Live, DataDependent, Live, Decorative],
+                [Decorative, Decorative, Live, Decorative, Unreachable],
+            ]
+        );
+        assert_eq!(
+            reach.to_string(),
+            "1 block never reached\n    at (4, 2): Yellow, 1 codel\n\
+             1 block only reached after a pointer or switch turns\n    at (2, 1): Green, 1 codel\n\
+             5 decorative codels\n"
+        );
+
+        // everything in a straight line program runs
+        let image = print_program("ok", Layout::Row);
+        let reach = Reach::new(&image, &Dialect::SPEC);
+        assert!(reach.unreachable.is_empty() && reach.data_dependent.is_empty());
+    }
+
+    #[test]
+    fn linter_finds_what_goes_wrong() {
+        let lints = |source: &str| -> Vec<(Lint, String)> {
+            lint(&assemble(source).unwrap(), &Dialect::SPEC)
+                .into_iter()
+                .map(|diagnostic| (diagnostic.lint, diagnostic.message))
+                .collect()
+        };
+        assert_eq!(lints("push 3\ndup\nmul\noutnum"), []);
+        assert_eq!(
+            lints("push 1\npop\nadd\noutnum"),
+            [
+                (
+                    Lint::Underflow,
+                    "add needs 2 values but the stack only ever has 0 here, it's ignored".into()
+                ),
+                (
+                    Lint::Underflow,
+                    "outnum needs 1 value but the stack only ever has 0 here, it's ignored".into()
+                ),
+            ]
+        );
+        assert_eq!(
+            lints("push 5\npush 1\ndup\nsub\nmod\noutnum"),
+            [(
+                Lint::DivisionByZero,
+                "mod by a constant zero is ignored".into()
+            )]
+        );
+        assert_eq!(
+            lints("push 1\npush 2\nsub\npush 1\nroll"),
+            [(
+                Lint::NegativeRoll,
+                "roll to a constant negative depth of -1 is ignored in Spec and npiet, stops the \
+                 program in PietDev"
+                    .into()
+            )]
+        );
+        assert_eq!(
+            lints("push 1\npush 8\nsub\npush 2\ndiv\nnum -1\noutchar"),
+            [
+                (
+                    Lint::Dialect,
+                    "div -7 by 2 is -4 in Spec and PietDev, is -3 in npiet".into()
+                ),
+                (
+                    Lint::Dialect,
+                    "-1 isn't a character, outputting it stops the program in Spec, prints \
+                     nothing in npiet, prints U+FFFD in PietDev"
+                        .into()
+                ),
+            ]
+        );
+        // what's read in isn't known, so neither is whether there's anything to add
+        assert_eq!(
+            lints("innum\nadd\noutnum"),
+            [(
+                Lint::Dialect,
+                "innum at the end of input waits for more in Spec, pushes -1 in npiet, pushes \
+                 nothing in PietDev"
+                    .into()
+            )]
+        );
+
+        // red slides right into white that turns it down to green, and green up into it and
+        // back, where PietDev would stop
+        let turned = draw(
+            "
+            r w .
+            . w .
+            . g .
+            ",
+        );
+        assert_eq!(
+            lint(&turned, &Dialect::SPEC)
+                .into_iter()
+                .map(|diagnostic| diagnostic.message)
+                .collect::<Vec<_>>(),
+            [
+                "moving into this white going right slides across, turning to dp down cc right \
+                 in Spec and npiet, stops in it in PietDev",
+                "moving into this white going up slides across, turning to dp down cc left in \
+                 Spec and npiet, stops in it in PietDev",
+                "moving into this white going up slides across, turning to dp down cc right in \
+                 Spec and npiet, stops in it in PietDev",
+            ]
+        );
+
+        // red slides into white that goes round in a circle, drawn with two pixel codels and
+        // one pixel off
+        let drawn = draw(
+            "
+            r w w .
+            . w w .
+            . . . .
+            ",
+        );
+        let rgb_at = |x: u32, y: u32| drawn.color(Codel::new(x / 2, y / 2)).rgb();
+        let mut rgb: Vec<u8> = (0..6)
+            .flat_map(|y| (0..8).flat_map(move |x| rgb_at(x, y)))
+            .collect();
+        let png = |rgb: &[u8]| {
+            let mut png = vec![];
+            let mut encoder = png::Encoder::new(&mut png, 8, 6);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder
+                .write_header()
+                .unwrap()
+                .write_image_data(rgb)
+                .unwrap();
+            png
+        };
+        // on the grid it loads scaled down
+        let image = PietImg::from_png(&png(&rgb)).unwrap();
+        assert_eq!((image.png_info.width, image.png_info.height), (4, 3));
+        assert_eq!(image.color(Codel::new(0, 0)), PietColor::Red);
+        assert_eq!(lint_png(&png(&rgb), None, &Dialect::SPEC).unwrap().len(), 1);
+
+        rgb[(5 * 8 + 7) * 3..(5 * 8 + 8) * 3].copy_from_slice(&[0x12, 0x34, 0x56]);
+        let diagnostics = lint_png(&png(&rgb), None, &Dialect::SPEC).unwrap();
+        assert_eq!(diagnostics, lint_pixels(8, 6, &rgb, None, &Dialect::SPEC));
+        assert_eq!(
+            to_text(&diagnostics),
+            "(2, 0) white-trap: sliding into this white never gets out, halting the program\n\
+             (6, 4) off-grid: this 2x2 codel isn't all one colour, a block edge is off the grid\n\
+             (7, 5) non-standard-color: 1 pixel of #123456 isn't a Piet colour, taken as white\n"
+        );
+        // a given codel size is kept to, unless the image can't be cut into codels that size
+        let lints = |size| -> Vec<Lint> {
+            let diagnostics = lint_pixels(8, 6, &rgb, Some(size), &Dialect::SPEC);
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.lint)
+                .collect()
+        };
+        assert!(!lints(1).contains(&Lint::OffGrid));
+        assert_eq!(
+            lints(2),
+            diagnostics.iter().map(|d| d.lint).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            to_text(&lint_pixels(8, 6, &rgb, Some(3), &Dialect::SPEC)[..1]),
+            "(0, 0) off-grid: an image of 8x6 pixels can't be cut into 3x3 codels\n"
+        );
+        assert_eq!(
+            PietImg::from_png(&png(&rgb)).err().unwrap(),
+            "the pixel at (7, 5) is #123456, which isn't a Piet colour"
+        );
+        assert!(lint_png(b"not a png", None, &Dialect::SPEC).is_err());
+        assert_eq!(
+            to_json(&diagnostics[..1]),
+            "[\n  {\"x\": 2, \"y\": 0, \"lint\": \"white-trap\", \"message\": \
+             \"sliding into this white never gets out, halting the program\"}\n]\n"
+        );
+    }
+
+    /// The loop `image` goes into given `input`, if it's found within `max_steps`
+    fn find_loop(image: PietImg, input: &str, max_steps: usize) -> Option<Loop> {
+        let mut env = PietEnv::new(image, Dialect::SPEC);
+        env.input.push_str(input);
+        let mut detector = LoopDetector::default();
+        while env.state == ExecState::Running && env.steps < max_steps {
+            env.step();
+            if let Some(found) = detector.observe(&env) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn loops_are_found_with_their_period() {
+        // red pushes going into dark red and dark red pops going back, with four restrictions
+        // on each side to turn round
+        let found = find_loop(draw("r dr"), "", 1_000).unwrap();
+        assert_eq!(found.period, 10);
+        assert_eq!(found.blocks, [Codel::new(0, 0), Codel::new(1, 0)]);
+        assert_eq!(
+            found.to_string(),
+            "infinite loop, repeats every 10 steps through blocks at (0, 0), (1, 0)"
+        );
+
+        let spin = assemble("loop:\npush 1\npop\njmp loop").unwrap();
+        let found = find_loop(spin.clone(), "", 1_000).unwrap();
+        // coming back round after a period is coming back to the same state
+        let mut env = PietEnv::new(spin, Dialect::SPEC);
+        while env.steps < 500 {
+            env.step();
+        }
+        let (stack, dp, cc, block) = (
+            env.stack.clone(),
+            env.dp,
+            env.cc,
+            env.image.block_id(env.cp),
+        );
+        for _ in 0..found.period {
+            env.step();
+        }
+        assert_eq!((env.stack, env.dp, env.cc), (stack, dp, cc));
+        assert_eq!(env.image.block_id(env.cp), block);
+
+        // halting, counting up forever and reading input every time round aren't loops
+        assert_eq!(
+            find_loop(print_program("done", Layout::Row), "", 100_000),
+            None
+        );
+        let count = assemble("push 1\nloop:\npush 1\nadd\njmp loop").unwrap();
+        assert_eq!(find_loop(count, "", 10_000), None);
+        let echo = assemble("loop:\ninchar\noutchar\njmp loop").unwrap();
+        assert_eq!(find_loop(echo, &"echo ".repeat(200), 100_000), None);
+    }
+
+    #[test]
+    fn dialects_follow_their_interpreters() {
+        let run_as = |source: &str, input: &str, dialect: Dialect| {
+            let mut env = PietEnv::new(assemble(source).unwrap(), dialect);
+            env.input.push_str(input);
+            while env.state == ExecState::Running && env.steps < 10_000 {
+                env.step();
+            }
+            env
+        };
+        // assembled programs are laid out with white to slide across
+        let piet_dev = Dialect {
+            white: White::Slide,
+            ..Dialect::PIET_DEV
+        };
+        let dialects = [Dialect::SPEC, Dialect::NPIET, piet_dev];
+        let outputs = |source: &str, input: &str| -> Vec<String> {
+            dialects
+                .iter()
+                .map(|dialect| run_as(source, input, *dialect).output)
+                .collect()
+        };
+        assert_eq!(
+            outputs("num -7\npush 2\ndiv\noutnum", ""),
+            ["-4", "-3", "-4"]
+        );
+        assert_eq!(outputs("num -7\npush 3\nmod\noutnum", ""), ["2", "2", "-1"]);
+        assert_eq!(outputs("inchar\noutnum", ""), ["", "-1", ""]);
+        assert_eq!(outputs("num -1\noutchar", ""), ["", "", "\u{fffd}"]);
+        let states: Vec<ExecState> = dialects
+            .iter()
+            .map(|dialect| run_as("push 1\nnum -1\npush 1\nroll", "", *dialect).state)
+            .collect();
+        assert_eq!(
+            states,
+            [
+                ExecState::Halted,
+                ExecState::Halted,
+                ExecState::Error("can't roll to a negative depth of -1".into())
+            ]
+        );
+        assert_eq!(
+            run_as("inchar\noutnum", "", Dialect::SPEC).state,
+            ExecState::WaitingForInput
+        );
+        assert!(matches!(
+            run_as("num -1\noutchar", "", Dialect::SPEC).state,
+            ExecState::Error(_)
+        ));
+
+        // each setting on its own
+        let custom = Dialect {
+            division: Rounding::TowardZero,
+            modulo: Sign::Dividend,
+            eof: Eof::Push(0),
+            invalid_char: InvalidChar::Ignore,
+            negative_roll: NegativeRoll::Ignore,
+            ..Dialect::SPEC
+        };
+        let env = run_as(
+            "num -7\npush 2\ndiv\noutnum\nnum -7\npush 2\nmod\noutnum\nnum -1\noutchar\n\
+             innum\noutnum",
+            "",
+            custom,
+        );
+        assert_eq!((env.state, env.output), (ExecState::Halted, "-3-10".into()));
+
+        // red slides across white and down into green, or stops in white and is restricted there
+        // before going down
+        let image = draw(
+            "
+            r w w
+            . . g
+            ",
+        );
+        let block = Dialect {
+            white: White::Block,
+            ..Dialect::SPEC
+        };
+        // stopping in white makes it a block of its own, which the back ends can't compile
+        let colors = |dialect: &Dialect| -> Vec<PietColor> {
+            let disassembly = disassemble(&image, dialect);
+            disassembly.blocks.iter().map(|block| block.color).collect()
+        };
+        assert_eq!(colors(&Dialect::SPEC), [PietColor::Red, PietColor::Green]);
+        assert_eq!(
+            colors(&block),
+            [PietColor::Red, PietColor::White, PietColor::Green]
+        );
+        let disassembly = disassemble(&image, &block);
+        let cfg = Cfg::new(&disassembly);
+        assert!(to_wat(&cfg).is_err() && to_rust(&cfg).is_err());
+        let mut slid = PietEnv::new(image.clone(), Dialect::SPEC);
+        slid.step();
+        let mut stopped = PietEnv::new(image, block);
+        for _ in 0..5 {
+            stopped.step();
+        }
+        for (env, cc, steps) in [
+            (slid, CodelChoser::Right, 1),
+            (stopped, CodelChoser::Left, 5),
+        ] {
+            assert_eq!(
+                (env.cp, env.dp, env.cc),
+                (Codel::new(2, 1), DirectionPointer::Down, cc)
+            );
+            assert_eq!(env.steps, steps);
+        }
+    }
+
+    /// Every `corpus/<name>.png`, run with `<name>.in` as its input if there is one, halts having
+    /// printed `<name>.out`, interpreted, stepped on its IR, as wasm and compiled to Rust. The
+    /// `.pasm` files are the assembly the others were made from.
+    #[test]
+    fn corpus_programs_print_what_they_should() {
+        let mut programs: Vec<_> = std::fs::read_dir("corpus")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+            .collect();
+        programs.sort();
+        assert!(programs.len() >= 7, "the corpus has gone missing");
+
+        let mut failures = vec![];
+        for path in programs {
+            let image = load(&path);
+            let name = format!("corpus-{}", path.file_stem().unwrap().to_string_lossy());
+            let input = std::fs::read_to_string(path.with_extension("in")).unwrap_or_default();
+            let expected = std::fs::read_to_string(path.with_extension("out")).unwrap();
+
+            let env = match cross_check(&image, &input, 100_000) {
+                Ok(env) => env,
+                Err(difference) => {
+                    failures.push(format!("{}: {}", path.display(), difference));
+                    continue;
+                }
+            };
+            if env.state != ExecState::Halted {
+                failures.push(format!("{}: {}", path.display(), env.state));
+            }
+            let (status, wasm) = run_wat(&image, &input);
+            if status != HALTED {
+                failures.push(format!("{}: wasm stopped with {}", path.display(), status));
+            }
+            let outputs = [
+                ("interpreted", env.output),
+                ("as wasm", wasm),
+                ("compiled", run_compiled(&image, &input, &name)),
+            ];
+            for (how, output) in outputs {
+                if output != expected {
+                    failures.push(format!(
+                        "{} {} printed {:?}, expected {:?}",
+                        path.display(),
+                        how,
+                        output,
+                        expected
+                    ));
+                }
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn exits_are_chosen_for_every_dp_and_cc() {
+        let exits = |art: &str, from: Codel| -> Vec<(Codel, u32)> {
+            let env = PietEnv::new(draw(art), Dialect::SPEC);
+            use DirectionPointer::{Down, Up};
+            [DirectionPointer::Right, Down, DirectionPointer::Left, Up]
+                .into_iter()
+                .flat_map(|dp| [(dp, CodelChoser::Left), (dp, CodelChoser::Right)])
+                .map(|(dp, cc)| env.get_block_transition(from, dp, cc))
+                .collect()
+        };
+
+        // every edge but the bottom one is in two pieces
+        let concave = "
+            . g . g .
+            g g g g g
+            . g . . .
+            g g . g .
+            . g g g g
+            ";
+        let expected = [
+            (Codel::new(4, 1), 15), // right, uppermost
+            (Codel::new(4, 4), 15), // right, lowermost
+            (Codel::new(4, 4), 15), // down, rightmost
+            (Codel::new(1, 4), 15), // down, leftmost
+            (Codel::new(0, 3), 15), // left, lowermost
+            (Codel::new(0, 1), 15), // left, uppermost
+            (Codel::new(1, 0), 15), // up, leftmost
+            (Codel::new(3, 0), 15), // up, rightmost
+        ];
+        for from in [Codel::new(1, 0), Codel::new(2, 4), Codel::new(0, 3)] {
+            assert_eq!(exits(concave, from), expected);
+        }
+
+        // a ring, each edge one piece with the hole's colour somewhere else
+        let ring = "
+            b b b r
+            b r b .
+            b b b .
+            ";
+        assert_eq!(
+            exits(ring, Codel::new(1, 2)),
+            [
+                (Codel::new(2, 0), 8),
+                (Codel::new(2, 2), 8),
+                (Codel::new(2, 2), 8),
+                (Codel::new(0, 2), 8),
+                (Codel::new(0, 2), 8),
+                (Codel::new(0, 0), 8),
+                (Codel::new(0, 0), 8),
+                (Codel::new(2, 0), 8),
+            ]
+        );
+        assert_eq!(exits(ring, Codel::new(1, 1)), [(Codel::new(1, 1), 1); 8]);
+
+        // white is left the same way, for dialects that stop in it
+        assert_eq!(
+            exits("w w\nw .", Codel::new(0, 0))[2..4],
+            [(Codel::new(0, 1), 3), (Codel::new(0, 1), 3)]
+        );
+    }
+
+    #[test]
+    fn exits_agree_with_brute_force() {
+        let mut random = random(0x9e37_79b9_7f4a_7c15);
+        // few colours, so blocks come out big and oddly shaped
+        let palette = [
+            PietColor::Red,
+            PietColor::Blue,
+            PietColor::White,
+            PietColor::Black,
+        ];
+        for _ in 0..500 {
+            let (width, height) = (1 + random(9), 1 + random(9));
+            let mut image = PietImg::filled(width, height, PietColor::Black);
+            for y in 0..height {
+                for x in 0..width {
+                    image.set(Codel::new(x, y), palette[random(4) as usize]);
+                }
+            }
+            let env = PietEnv::new(image.clone(), Dialect::SPEC);
+
+            for y in 0..height {
+                for x in 0..width {
+                    let from = Codel::new(x, y);
+                    let color = image.color(from);
+                    if color == PietColor::Black {
+                        continue;
+                    }
+                    // every codel of the same colour that can be reached from this one
+                    let mut block = vec![from];
+                    let mut i = 0;
+                    while i < block.len() {
+                        let codel = block[i];
+                        for (dx, dy) in [(1, 0), (0, 1), (-1, 0), (0, -1)] {
+                            let (nx, ny) = (codel.x as i64 + dx, codel.y as i64 + dy);
+                            if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                                continue;
+                            }
+                            let next = Codel::new(nx as u32, ny as u32);
+                            if image.color(next) == color && !block.contains(&next) {
+                                block.push(next);
+                            }
+                        }
+                        i += 1;
+                    }
+
+                    for dp in [
+                        DirectionPointer::Right,
+                        DirectionPointer::Down,
+                        DirectionPointer::Left,
+                        DirectionPointer::Up,
+                    ] {
+                        for cc in [CodelChoser::Left, CodelChoser::Right] {
+                            // furthest along the DP, then furthest towards the CC's side of it
+                            let along = |codel: &Codel, dp: DirectionPointer| match dp {
+                                DirectionPointer::Right => codel.x as i64,
+                                DirectionPointer::Down => codel.y as i64,
+                                DirectionPointer::Left => -(codel.x as i64),
+                                DirectionPointer::Up => -(codel.y as i64),
+                            };
+                            // left of the DP is the other way from right of it
+                            let side = |codel: &Codel| match cc {
+                                CodelChoser::Left => -along(codel, dp.clockwise()),
+                                CodelChoser::Right => along(codel, dp.clockwise()),
+                            };
+                            let exit = block
+                                .iter()
+                                .max_by_key(|codel| (along(codel, dp), side(codel)))
+                                .unwrap();
+                            assert_eq!(
+                                env.get_block_transition(from, dp, cc),
+                                (*exit, block.len() as u32),
+                                "leaving {:?} {:?} {:?} in\n{:?}",
+                                from,
+                                dp,
+                                cc,
+                                image.bytes()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,575 @@
+use crate::asm::mnemonic;
+use crate::cfg::Cfg;
+use crate::dialect::{Dialect, Eof, InvalidChar, NegativeRoll, White};
+use crate::disasm::{chooser, direction, disassemble, Outcome};
+use crate::env::{PietEnv, PietOp};
+use crate::image::{decode_png, PietImg};
+use crate::ty::*;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+/// A kind of problem the linter looks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    /// Pixels that aren't one of the twenty Piet colours
+    NonStandardColor,
+    /// Codels that aren't all one colour, in an image that looks like it's scaled up
+    OffGrid,
+    /// White that's slid into and never left, halting the program
+    WhiteTrap,
+    /// A command that always finds too few values on the stack and is ignored
+    Underflow,
+    /// Divide or mod by a value that's always zero, which is ignored
+    DivisionByZero,
+    /// Roll with a depth that's always negative, which is ignored
+    NegativeRoll,
+    /// Something interpreters don't agree on
+    Dialect,
+}
+
+impl Lint {
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::NonStandardColor => "non-standard-color",
+            Lint::OffGrid => "off-grid",
+            Lint::WhiteTrap => "white-trap",
+            Lint::Underflow => "underflow",
+            Lint::DivisionByZero => "division-by-zero",
+            Lint::NegativeRoll => "negative-roll",
+            Lint::Dialect => "dialect",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Diagnostic {
+    /// The pixel it's about, the top left one of its codel in a scaled up image
+    pub y: u32,
+    pub x: u32,
+    pub lint: Lint,
+    pub message: String,
+}
+
+/// Share of an image's codels that have to be one colour for it to be taken as scaled up
+const GRID_THRESHOLD: f64 = 0.9;
+
+/// Lint an image given as 8 bit RGB pixels, row by row, including the things that can't be
+/// loaded as a program. Without a `codel_size`, or one the image can't be cut into, it's taken
+/// to be the largest that every codel, or failing that nearly every one, is a single colour at.
+/// Other colours are taken as white for the rest of the checks, which are made on the image
+/// scaled down to one pixel a codel.
+pub fn lint_pixels(
+    width: u32,
+    height: u32,
+    rgb: &[u8],
+    codel_size: Option<u32>,
+    dialect: &Dialect,
+) -> Vec<Diagnostic> {
+    let pixel = |x: u32, y: u32| {
+        let i = ((y * width + x) * 3) as usize;
+        u32::from_be_bytes([0, rgb[i], rgb[i + 1], rgb[i + 2]])
+    };
+    let color = |x: u32, y: u32| -> Option<PietColor> { num::FromPrimitive::from_u32(pixel(x, y)) };
+    let mut diagnostics = vec![];
+
+    // the first of each colour that isn't a Piet one, and how many there are
+    let mut strange: BTreeMap<u32, (Codel, usize)> = BTreeMap::new();
+    for y in 0..height {
+        for x in 0..width {
+            if color(x, y).is_none() {
+                strange
+                    .entry(pixel(x, y))
+                    .or_insert((Codel::new(x, y), 0))
+                    .1 += 1;
+            }
+        }
+    }
+    for (rgb, (at, count)) in strange {
+        diagnostics.push(Diagnostic {
+            x: at.x,
+            y: at.y,
+            lint: Lint::NonStandardColor,
+            message: format!(
+                "{} pixel{} of #{:06X} {} a Piet colour, taken as white",
+                count,
+                if count == 1 { "" } else { "s" },
+                rgb,
+                if count == 1 { "isn't" } else { "aren't" }
+            ),
+        });
+    }
+
+    // codels of `size` pixels that aren't one colour
+    let mixed = |size: u32| -> Vec<Codel> {
+        let mut mixed = vec![];
+        for y in (0..height).step_by(size as usize) {
+            for x in (0..width).step_by(size as usize) {
+                let first = pixel(x, y);
+                if (y..y + size).any(|y| (x..x + size).any(|x| pixel(x, y) != first)) {
+                    mixed.push(Codel::new(x, y));
+                }
+            }
+        }
+        mixed
+    };
+    let fits = |size: u32| size > 0 && width.is_multiple_of(size) && height.is_multiple_of(size);
+    let sizes: Vec<u32> = (2..=width.min(height))
+        .rev()
+        .filter(|size| fits(*size))
+        .collect();
+    let codels = |size: u32| ((width / size) * (height / size)) as f64;
+    let given = match codel_size {
+        Some(size) if !fits(size) => {
+            diagnostics.push(Diagnostic {
+                x: 0,
+                y: 0,
+                lint: Lint::OffGrid,
+                message: format!(
+                    "an image of {}x{} pixels can't be cut into {2}x{2} codels",
+                    width, height, size
+                ),
+            });
+            None
+        }
+        given => given,
+    };
+    let size = match (given, crate::image::codel_size(width, height, rgb)) {
+        (None, exact) if exact > 1 => Some(exact),
+        (Some(size), _) => Some(size),
+        (None, _) => sizes
+            .iter()
+            .copied()
+            .find(|size| 1.0 - mixed(*size).len() as f64 / codels(*size) >= GRID_THRESHOLD),
+    };
+    if let Some(size) = size {
+        for at in mixed(size) {
+            diagnostics.push(Diagnostic {
+                x: at.x,
+                y: at.y,
+                lint: Lint::OffGrid,
+                message: format!(
+                    "this {0}x{0} codel isn't all one colour, a block edge is off the grid",
+                    size
+                ),
+            });
+        }
+    }
+    let size = size.unwrap_or(1);
+
+    let mut image = PietImg::filled(width / size, height / size, PietColor::White);
+    for y in 0..height / size {
+        for x in 0..width / size {
+            if let Some(color) = color(x * size, y * size) {
+                image.set(Codel::new(x, y), color);
+            }
+        }
+    }
+    for diagnostic in lint(&image, dialect) {
+        diagnostics.push(Diagnostic {
+            x: diagnostic.x * size,
+            y: diagnostic.y * size,
+            ..diagnostic
+        });
+    }
+    diagnostics.sort();
+    diagnostics
+}
+
+/// Lint a png file, which needn't load as a program, the way `lint_pixels` does. It's only an
+/// error if it can't be decoded.
+pub fn lint_png(
+    png: &[u8],
+    codel_size: Option<u32>,
+    dialect: &Dialect,
+) -> Result<Vec<Diagnostic>, String> {
+    let (width, height, rgb) = decode_png(png)?;
+    Ok(lint_pixels(width, height, &rgb, codel_size, dialect))
+}
+
+/// What's known about the stack when a state is left: how deep it is, if that's always the
+/// same, and what each value is, where that's always the same
+type Stack = Option<Vec<Option<i64>>>;
+
+/// Deeper than this, the stack's taken as unknown so loops that push don't run on forever
+const MAX_DEPTH: usize = 1024;
+
+fn join(a: &Stack, b: &Stack) -> Stack {
+    match (a, b) {
+        (Some(a), Some(b)) if a.len() == b.len() => Some(
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| if a == b { *a } else { None })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// The preset dialects' names, joined up in groups that `f` gives the same result for
+fn by_dialect<T: PartialEq>(f: impl Fn(&Dialect) -> T) -> Vec<(T, String)> {
+    let mut groups: Vec<(T, Vec<&str>)> = vec![];
+    for (name, dialect) in Dialect::PRESETS {
+        let result = f(&dialect);
+        match groups.iter_mut().find(|(found, _)| *found == result) {
+            Some((_, names)) => names.push(name),
+            None => groups.push((result, vec![name])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(result, names)| (result, names.join(" and ")))
+        .collect()
+}
+
+/// Say what each group of dialects does, or just what they all do if they agree
+fn describe<T, S: std::fmt::Display>(groups: Vec<(T, String)>, say: impl Fn(T) -> S) -> String {
+    if groups.len() == 1 {
+        let (result, _) = groups.into_iter().next().unwrap();
+        return say(result).to_string();
+    }
+    let said: Vec<String> = groups
+        .into_iter()
+        .map(|(result, names)| format!("{} in {}", say(result), names))
+        .collect();
+    said.join(", ")
+}
+
+/// Run `op` leaving a block of `size` codels on what's known about the stack the way `dialect`
+/// does, reporting what's wrong with it
+fn execute(
+    op: PietOp,
+    size: u32,
+    dialect: &Dialect,
+    stack: &mut Stack,
+    report: &mut impl FnMut(Lint, String),
+) {
+    if matches!(op, PietOp::InNumber | PietOp::InChar) {
+        let results = by_dialect(|dialect| dialect.eof);
+        if results.len() > 1 {
+            let results = describe(results, |result| match result {
+                Eof::Wait => "waits for more".to_string(),
+                Eof::Ignore => "pushes nothing".to_string(),
+                Eof::Push(val) => format!("pushes {}", val),
+            });
+            report(
+                Lint::Dialect,
+                format!("{} at the end of input {}", mnemonic(op), results),
+            );
+        }
+    }
+    // once its depth isn't known, no command can tell us what it is again
+    let Some(values) = stack else {
+        return;
+    };
+    let needs = match op {
+        PietOp::None | PietOp::Push | PietOp::InNumber | PietOp::InChar => 0,
+        PietOp::Pop
+        | PietOp::Not
+        | PietOp::Pointer
+        | PietOp::Switch
+        | PietOp::Duplicate
+        | PietOp::OutNumber
+        | PietOp::OutChar => 1,
+        _ => 2,
+    };
+    if values.len() < needs {
+        report(
+            Lint::Underflow,
+            format!(
+                "{} needs {} value{} but the stack only ever has {} here, it's ignored",
+                mnemonic(op),
+                needs,
+                if needs == 1 { "" } else { "s" },
+                values.len()
+            ),
+        );
+        return;
+    }
+    let binary = |values: &mut Vec<Option<i64>>, f: fn(i64, i64) -> i64| {
+        let b = values.pop().unwrap();
+        let a = values.pop().unwrap();
+        values.push(a.zip(b).map(|(a, b)| f(a, b)));
+    };
+    match op {
+        PietOp::None => {}
+        PietOp::Push => values.push(Some(size as i64)),
+        PietOp::Pop | PietOp::Pointer | PietOp::Switch | PietOp::OutNumber => {
+            values.pop();
+        }
+        PietOp::Add => binary(values, i64::wrapping_add),
+        PietOp::Subtract => binary(values, i64::wrapping_sub),
+        PietOp::Multiply => binary(values, i64::wrapping_mul),
+        PietOp::Greater => binary(values, |a, b| (a > b) as i64),
+        PietOp::Not => {
+            let top = values.last_mut().unwrap();
+            *top = top.map(|val| (val == 0) as i64);
+        }
+        PietOp::Duplicate => values.push(*values.last().unwrap()),
+        PietOp::Divide | PietOp::Mod => {
+            let len = values.len();
+            match (values[len - 2], values[len - 1]) {
+                (_, Some(0)) => report(
+                    Lint::DivisionByZero,
+                    format!("{} by a constant zero is ignored", mnemonic(op)),
+                ),
+                // it's ignored when it's by zero, leaving the stack as deep as it was
+                (_, None) => *stack = None,
+                (a, Some(b)) => {
+                    let result = |dialect: &Dialect, a| match op {
+                        PietOp::Divide => dialect.divide(a, b),
+                        _ => dialect.remainder(a, b),
+                    };
+                    if let Some(a) = a {
+                        let results = by_dialect(|dialect| result(dialect, a));
+                        if results.len() > 1 {
+                            let results = describe(results, |result| match result {
+                                Some(result) => format!("is {}", result),
+                                None => "is ignored".to_string(),
+                            });
+                            report(
+                                Lint::Dialect,
+                                format!("{} {} by {} {}", mnemonic(op), a, b, results),
+                            );
+                        }
+                    }
+                    let result = a.map(|a| result(dialect, a));
+                    if result == Some(None) {
+                        // overflows, so it's ignored
+                        return;
+                    }
+                    values.truncate(len - 2);
+                    values.push(result.flatten());
+                }
+            }
+        }
+        PietOp::Roll => {
+            let len = values.len() - 2;
+            match (values[len], values[len + 1]) {
+                (Some(depth), _) if depth < 0 => {
+                    let results =
+                        describe(
+                            by_dialect(|dialect| dialect.negative_roll),
+                            |result| match result {
+                                NegativeRoll::Ignore => "is ignored",
+                                NegativeRoll::Error => "stops the program",
+                            },
+                        );
+                    report(
+                        Lint::NegativeRoll,
+                        format!("roll to a constant negative depth of {} {}", depth, results),
+                    )
+                }
+                (Some(depth), _) if depth as usize > len => report(
+                    Lint::Underflow,
+                    format!(
+                        "roll {} deep but the stack only ever has {} under it here, it's ignored",
+                        depth, len
+                    ),
+                ),
+                (Some(depth), rolls) => {
+                    values.truncate(len);
+                    if depth > 0 {
+                        let start = len - depth as usize;
+                        match rolls {
+                            Some(rolls) => {
+                                values[start..].rotate_right(rolls.rem_euclid(depth) as usize)
+                            }
+                            None => values[start..].fill(None),
+                        }
+                    }
+                }
+                (None, _) => *stack = None,
+            }
+        }
+        // a number might not be read
+        PietOp::InNumber => *stack = None,
+        PietOp::InChar => values.push(None),
+        PietOp::OutChar => {
+            if let Some(val) = values.pop().unwrap() {
+                let results = by_dialect(|dialect| dialect.invalid_char);
+                if u32::try_from(val).ok().and_then(char::from_u32).is_none() && results.len() > 1 {
+                    let results = describe(results, |result| match result {
+                        InvalidChar::Error => "stops the program",
+                        InvalidChar::Ignore => "prints nothing",
+                        InvalidChar::Replace => "prints U+FFFD",
+                    });
+                    report(
+                        Lint::Dialect,
+                        format!("{} isn't a character, outputting it {}", val, results),
+                    );
+                }
+            }
+        }
+    }
+    if stack
+        .as_ref()
+        .is_some_and(|values| values.len() > MAX_DEPTH)
+    {
+        *stack = None;
+    }
+}
+
+/// Lint a program run as `dialect`: where it gets trapped in white, what can't help going wrong
+/// with the stack, and what other interpreters would run differently
+pub fn lint(image: &PietImg, dialect: &Dialect) -> Vec<Diagnostic> {
+    let disassembly = disassemble(image, dialect);
+    let cfg = Cfg::new(&disassembly);
+    let mut diagnostics = HashSet::new();
+    let mut env = PietEnv::new(image.clone(), *dialect);
+
+    // the codel each state leaves from, and what's in front of it
+    let mut exits = vec![];
+    for state in &cfg.states {
+        env.cp = disassembly.blocks[state.block].codel;
+        env.dp = state.dp;
+        env.cc = state.cc;
+        let exit = env.next_transition().exit;
+        exits.push((exit, exit.block_in_dir(state.dp)));
+    }
+    let outcomes = disassembly
+        .blocks
+        .iter()
+        .flat_map(|block| block.exits.iter().map(|(_, _, outcome)| outcome));
+    let whites = by_dialect(|dialect| dialect.white);
+    for ((state, (_, front)), outcome) in cfg.states.iter().zip(&exits).zip(outcomes) {
+        let (lint, message) = match outcome {
+            Outcome::Trapped => (
+                Lint::WhiteTrap,
+                "sliding into this white never gets out, halting the program".to_string(),
+            ),
+            Outcome::Move {
+                op: PietOp::None,
+                dp,
+                cc,
+                ..
+            } if (*dp, *cc) != (state.dp, state.cc) && whites.len() > 1 => {
+                let results = describe(whites.clone(), |white| match white {
+                    White::Slide => format!(
+                        "slides across, turning to dp {} cc {}",
+                        direction(*dp),
+                        chooser(*cc)
+                    ),
+                    White::Block => "stops in it".to_string(),
+                });
+                (
+                    Lint::Dialect,
+                    format!(
+                        "moving into this white going {} {}",
+                        direction(state.dp),
+                        results
+                    ),
+                )
+            }
+            _ => continue,
+        };
+        let at = front.unwrap();
+        diagnostics.insert(Diagnostic {
+            x: at.x,
+            y: at.y,
+            lint,
+            message,
+        });
+    }
+
+    // what's known about the stack going into each state, from the start on
+    let edges = cfg.edges_from();
+    let mut stacks: Vec<Option<Stack>> = vec![None; cfg.states.len()];
+    let mut queue = VecDeque::new();
+    if !stacks.is_empty() {
+        stacks[0] = Some(Some(vec![]));
+        queue.push_back(0);
+    }
+    while let Some(state) = queue.pop_front() {
+        let mut stack = stacks[state].clone().unwrap();
+        let size = cfg.block(state).size as u32;
+        let (popped, taken) = match edges[state].first().and_then(|edge| edge.command) {
+            Some(op @ (PietOp::Pointer | PietOp::Switch)) => {
+                // with nothing to pop, it doesn't turn
+                let top = stack
+                    .as_ref()
+                    .map(|values| values.last().copied().unwrap_or(Some(0)));
+                execute(op, size, dialect, &mut stack, &mut |_, _| {});
+                (top, edges[state].len() as i64)
+            }
+            Some(op) => {
+                execute(op, size, dialect, &mut stack, &mut |_, _| {});
+                (None, 0)
+            }
+            None => (None, 0),
+        };
+        for edge in &edges[state] {
+            // a popped value that's always the same always turns the same way
+            if let (Some(Some(val)), Some(branch)) = (popped, edge.branch) {
+                if val.rem_euclid(taken) != branch {
+                    continue;
+                }
+            }
+            let joined = match &stacks[edge.to] {
+                Some(known) => join(known, &stack),
+                None => stack.clone(),
+            };
+            if stacks[edge.to].as_ref() != Some(&joined) {
+                stacks[edge.to] = Some(joined);
+                queue.push_back(edge.to);
+            }
+        }
+    }
+    for (state, stack) in stacks.into_iter().enumerate() {
+        let (Some(mut stack), Some(op)) = (stack, edges[state].first().and_then(|e| e.command))
+        else {
+            continue;
+        };
+        let (at, _) = exits[state];
+        let size = cfg.block(state).size as u32;
+        execute(op, size, dialect, &mut stack, &mut |lint, message| {
+            diagnostics.insert(Diagnostic {
+                x: at.x,
+                y: at.y,
+                lint,
+                message,
+            });
+        });
+    }
+    let mut diagnostics: Vec<Diagnostic> = diagnostics.into_iter().collect();
+    diagnostics.sort();
+    diagnostics
+}
+
+/// One diagnostic a line, each with where it is and what kind it is
+pub fn to_text(diagnostics: &[Diagnostic]) -> String {
+    let mut out = String::new();
+    for diagnostic in diagnostics {
+        writeln!(
+            out,
+            "({}, {}) {}: {}",
+            diagnostic.x,
+            diagnostic.y,
+            diagnostic.lint.name(),
+            diagnostic.message
+        )
+        .unwrap();
+    }
+    out
+}
+
+pub fn to_json(diagnostics: &[Diagnostic]) -> String {
+    let diagnostics: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            format!(
+                "{{\"x\": {}, \"y\": {}, \"lint\": \"{}\", \"message\": \"{}\"}}",
+                diagnostic.x,
+                diagnostic.y,
+                diagnostic.lint.name(),
+                diagnostic
+                    .message
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+            )
+        })
+        .collect();
+    if diagnostics.is_empty() {
+        return "[]\n".to_string();
+    }
+    format!("[\n  {}\n]\n", diagnostics.join(",\n  "))
+}
@@ -0,0 +1,126 @@
+use crate::env::{ExecState, PietEnv};
+use crate::ty::{Codel, CodelChoser, DirectionPointer};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A cycle a program has gone into and can never leave
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    /// Steps it takes to come back round
+    pub period: usize,
+    /// The first codel of each colour block it goes through, in the order they come round
+    pub blocks: Vec<Codel>,
+}
+
+impl std::fmt::Display for Loop {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "infinite loop, repeats every {} step{} through block{} at",
+            self.period,
+            if self.period == 1 { "" } else { "s" },
+            if self.blocks.len() == 1 { "" } else { "s" }
+        )?;
+        for (i, codel) in self.blocks.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(f, "{} ({}, {})", separator, codel.x, codel.y)?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything that decides what a program does next, apart from its input
+#[derive(Debug, Clone, PartialEq, Hash)]
+struct Snapshot {
+    block: usize,
+    dp: DirectionPointer,
+    cc: CodelChoser,
+    restricted: usize,
+    stack: Vec<i64>,
+}
+
+impl Snapshot {
+    fn new(env: &PietEnv) -> Self {
+        Snapshot {
+            block: env.image.block_id(env.cp),
+            dp: env.dp,
+            cc: env.cc,
+            restricted: env.flow_restricted_count,
+            stack: env.stack.to_vec(),
+        }
+    }
+}
+
+/// Spots a program coming back to a state it was in without having read anything since, which
+/// it'll then repeat forever.
+///
+/// Only one state is kept to compare against, by its hash first, and it's moved on to the
+/// current one after twice as many steps each time (Brent's algorithm), so a loop is found
+/// within a few times its period of it starting.
+#[derive(Default)]
+pub struct LoopDetector {
+    /// The state compared against and its hash
+    saved: Option<(Snapshot, u64)>,
+    /// Steps until the state compared against moves on, and how many there have been
+    power: usize,
+    length: usize,
+    /// Colour blocks gone through since the saved state
+    trail: Vec<usize>,
+    /// Input read and steps taken as of the last step observed
+    read: usize,
+    steps: usize,
+}
+
+impl LoopDetector {
+    /// Look at where `env` is after a step, finding the loop it's in if it's been here before
+    /// without reading any input since. Starts over when it's not the step after the last one
+    /// looked at, or the program's reset, stopped or read something.
+    pub fn observe(&mut self, env: &PietEnv) -> Option<Loop> {
+        let carried_on = env.steps == self.steps + 1 && env.input_read.len() == self.read;
+        self.steps = env.steps;
+        self.read = env.input_read.len();
+        if env.state != ExecState::Running {
+            self.saved = None;
+            return None;
+        }
+        let snapshot = Snapshot::new(env);
+        let mut hasher = DefaultHasher::new();
+        snapshot.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        match &self.saved {
+            Some((saved, saved_hash)) if carried_on => {
+                self.length += 1;
+                self.trail.push(snapshot.block);
+                if *saved_hash == hash && *saved == snapshot {
+                    let mut blocks = vec![];
+                    for block in &self.trail {
+                        let codel = env.image.blocks().blocks[*block].codels[0];
+                        if !blocks.contains(&codel) {
+                            blocks.push(codel);
+                        }
+                    }
+                    return Some(Loop {
+                        period: self.length,
+                        blocks,
+                    });
+                }
+                if self.length == self.power {
+                    self.power *= 2;
+                    self.save(snapshot, hash);
+                }
+            }
+            _ => {
+                self.power = 1;
+                self.save(snapshot, hash);
+            }
+        }
+        None
+    }
+
+    fn save(&mut self, snapshot: Snapshot, hash: u64) {
+        self.saved = Some((snapshot, hash));
+        self.length = 0;
+        self.trail.clear();
+    }
+}
@@ -1,11 +1,319 @@
+use crate::dialect::Dialect;
+use crate::editor::Tool;
+use crate::env::ExecState;
+use crate::image::PietImg;
+use crate::reach::{Reach, Role};
+use crate::ty::{Codel, CodelChoser, DirectionPointer};
 use crate::AppData;
+use druid::kurbo::BezPath;
+use druid::piet::{
+    FontFamily, ImageFormat, InterpolationMode, PietImage, Text, TextLayout, TextLayoutBuilder,
+};
 use druid::{
-    BoxConstraints, Color, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx,
-    Point, Rect, RenderContext, Size, UpdateCtx, Widget,
+    BoxConstraints, Color, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx,
+    PaintCtx, Point, Rect, RenderContext, Selector, Size, UpdateCtx, Vec2, Widget,
 };
+use std::collections::HashSet;
+
+/// Zoom and centre the program so all of it fits in the view
+pub const FIT_TO_WINDOW: Selector = Selector::new("cornelis.fit-to-window");
+/// The mouse has left the view, so it's no longer over any codel
+const UNHOVER: Selector = Selector::new("cornelis.unhover");
+
+/// Smallest and largest a codel can be zoomed to, in pixels
+const MIN_ZOOM: f64 = 0.05;
+const MAX_ZOOM: f64 = 256.0;
+/// How far the mouse can move with the button held before a click becomes a drag
+const DRAG_THRESHOLD: f64 = 3.0;
+/// How close the codel pointer may get to the edge of the view when following it, in codels
+const FOLLOW_MARGIN: f64 = 2.0;
+
+const BREAKPOINT: Color = Color::rgb8(0xe0, 0x20, 0x20);
+/// Drawn under the highlights so they show up on light and dark codels alike
+const HALO: Color = Color::rgba8(0x00, 0x00, 0x00, 0xb0);
+const CURRENT_BLOCK: Color = Color::WHITE;
+const NEXT_BLOCK: Color = Color::rgb8(0xff, 0xd7, 0x00);
+const HOVER: Color = Color::rgb8(0xa0, 0xa0, 0xa0);
+const EXIT: Color = Color::rgb8(0x30, 0xe0, 0xff);
+/// How opaque the mask over dead code is, and over code only reached by turning
+const DEAD: u8 = 0xc0;
+const DATA_DEPENDENT: u8 = 0x60;
+
+enum Gesture {
+    /// The button went down here, it's a click unless it moves far enough to become a pan
+    Press(Point),
+    /// Dragging the view around, from where the mouse last was
+    Pan(Point),
+    /// Painting a stroke in the editor, from the last codel painted
+    Paint(Codel),
+}
 
 pub struct PietViewWidget {
-    pub cell_size: Size,
+    /// Side of a codel on screen, codels are always square so the program keeps its aspect ratio
+    zoom: f64,
+    /// Where the top left corner of the program is drawn
+    origin: Point,
+    /// Keep the whole program fitted to the view until the user zooms or pans
+    fit: bool,
+    /// What the mouse is doing while a button is held
+    gesture: Option<Gesture>,
+    /// The program rendered as a bitmap, rebuilt when the image changes
+    bitmap: Option<(PietImg, PietImage)>,
+    /// What's dead in the program drawn as a mask and summed up, rebuilt when the image or
+    /// dialect changes
+    dead: Option<(PietImg, Dialect, PietImage, String)>,
+}
+
+impl PietViewWidget {
+    pub fn new() -> Self {
+        PietViewWidget {
+            zoom: 1.0,
+            origin: Point::ORIGIN,
+            fit: true,
+            gesture: None,
+            bitmap: None,
+            dead: None,
+        }
+    }
+
+    /// The codel under a point in widget coordinates, if any
+    fn codel_at(&self, pos: Point, data: &AppData) -> Option<Codel> {
+        let pos = (pos - self.origin) / self.zoom;
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return None;
+        }
+        let codel = Codel::new(pos.x as u32, pos.y as u32);
+        Some(codel).filter(|codel| data.env.image.contains(*codel))
+    }
+
+    fn codel_rect(&self, codel: Codel) -> Rect {
+        let origin = self.origin + Vec2::new(codel.x as f64, codel.y as f64) * self.zoom;
+        Rect::from_origin_size(origin, (self.zoom, self.zoom))
+    }
+
+    fn image_rect(&self, image: &PietImg) -> Rect {
+        let size = Size::new(image.png_info.width as f64, image.png_info.height as f64);
+        Rect::from_origin_size(self.origin, size * self.zoom)
+    }
+
+    fn fit_to(&mut self, view: Size, image: &PietImg) {
+        let (width, height) = (image.png_info.width as f64, image.png_info.height as f64);
+        self.zoom = (view.width / width)
+            .min(view.height / height)
+            .clamp(MIN_ZOOM, MAX_ZOOM);
+        self.origin = Point::new(
+            (view.width - width * self.zoom) / 2.0,
+            (view.height - height * self.zoom) / 2.0,
+        );
+    }
+
+    /// Scale by `factor` keeping the point under `anchor` where it is
+    fn zoom_around(&mut self, anchor: Point, factor: f64) {
+        let zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.origin = anchor - (anchor - self.origin) * (zoom / self.zoom);
+        self.zoom = zoom;
+        self.fit = false;
+    }
+
+    /// Pan so the codel is comfortably inside the view, centring it if it went off screen
+    fn follow(&mut self, view: Size, codel: Codel) {
+        let margin = self.zoom * FOLLOW_MARGIN;
+        let visible = view.to_rect().inset(-margin);
+        let rect = self.codel_rect(codel);
+        if visible.width() <= 0.0
+            || visible.height() <= 0.0
+            || visible.contains(rect.origin()) && visible.contains(Point::new(rect.x1, rect.y1))
+        {
+            return;
+        }
+        self.origin += view.to_rect().center() - rect.center();
+        self.fit = false;
+    }
+
+    /// Trace the sides of the block's codels that don't touch another codel of the block
+    fn block_outline(&self, codels: &[Codel]) -> BezPath {
+        let block: HashSet<Codel> = codels.iter().copied().collect();
+        let mut path = BezPath::new();
+        for codel in codels {
+            let rect = self.codel_rect(*codel);
+            for dir in &[
+                DirectionPointer::Up,
+                DirectionPointer::Down,
+                DirectionPointer::Left,
+                DirectionPointer::Right,
+            ] {
+                if codel
+                    .block_in_dir(*dir)
+                    .is_some_and(|next| block.contains(&next))
+                {
+                    continue;
+                }
+                let (from, to) = match dir {
+                    DirectionPointer::Up => ((rect.x0, rect.y0), (rect.x1, rect.y0)),
+                    DirectionPointer::Down => ((rect.x0, rect.y1), (rect.x1, rect.y1)),
+                    DirectionPointer::Left => ((rect.x0, rect.y0), (rect.x0, rect.y1)),
+                    DirectionPointer::Right => ((rect.x1, rect.y0), (rect.x1, rect.y1)),
+                };
+                path.move_to(from);
+                path.line_to(to);
+            }
+        }
+        path
+    }
+
+    /// Dim codels that never run or play no part, less so those only reached by turning, and
+    /// say how many there are
+    fn paint_dead(&mut self, ctx: &mut PaintCtx, image: &PietImg, dialect: &Dialect) {
+        if !matches!(&self.dead, Some((cached, was, _, _)) if cached.same(image) && was == dialect)
+        {
+            let reach = Reach::new(image, dialect);
+            let (width, height) = (image.png_info.width, image.png_info.height);
+            let mut mask = Vec::with_capacity((width * height * 4) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let alpha = match reach.role(Codel::new(x, y)) {
+                        Role::Live => 0,
+                        Role::DataDependent => DATA_DEPENDENT,
+                        Role::Unreachable | Role::Decorative => DEAD,
+                    };
+                    mask.extend([0, 0, 0, alpha]);
+                }
+            }
+            let mask = ctx
+                .make_image(
+                    width as usize,
+                    height as usize,
+                    &mask,
+                    ImageFormat::RgbaSeparate,
+                )
+                .unwrap();
+            let summary = format!(
+                "{} unreachable, {} reached by turning, {} decorative",
+                reach.unreachable.len(),
+                reach.data_dependent.len(),
+                reach.decorative
+            );
+            self.dead = Some((image.clone(), *dialect, mask, summary));
+        }
+        if let Some((_, _, mask, summary)) = &self.dead {
+            ctx.draw_image(
+                mask,
+                self.image_rect(image),
+                InterpolationMode::NearestNeighbor,
+            );
+            let layout = ctx
+                .text()
+                .new_text_layout(summary.clone())
+                .font(FontFamily::SYSTEM_UI, 12.0)
+                .text_color(HOVER)
+                .build()
+                .unwrap();
+            let origin = Point::new(8.0, 8.0);
+            let background = Rect::from_origin_size(origin, layout.size()).inset(3.0);
+            ctx.fill(background.to_rounded_rect(3.0), &HALO);
+            ctx.draw_text(&layout, origin);
+        }
+    }
+
+    /// Outline the current block, the exit codel, the DP/CC and where the next step will go
+    fn paint_transition(&self, ctx: &mut PaintCtx, data: &AppData) {
+        let env = &data.env;
+        let transition = env.next_transition();
+        let cell = self.zoom;
+
+        let current = env.image.block(env.cp);
+        let outline = self.block_outline(&current.codels);
+        ctx.stroke(&outline, &HALO, 4.0);
+        ctx.stroke(&outline, &CURRENT_BLOCK, 2.0);
+
+        if let Some(entry) = transition.entry {
+            let next = env.image.block(entry);
+            let outline = self.block_outline(&next.codels);
+            ctx.stroke(&outline, &HALO, 4.0);
+            ctx.stroke(&outline, &NEXT_BLOCK, 2.0);
+        }
+
+        let exit = self.codel_rect(transition.exit);
+        ctx.stroke(exit.inset(-cell * 0.15), &HALO, 3.0);
+        ctx.stroke(exit.inset(-cell * 0.15), &EXIT, 1.5);
+
+        let dp = direction(env.dp);
+        let cc = match env.cc {
+            CodelChoser::Left => Vec2::new(dp.y, -dp.x),
+            CodelChoser::Right => Vec2::new(-dp.y, dp.x),
+        };
+        let center = exit.center();
+        let dp_arrow = arrow(center, dp, cell);
+        let cc_arrow = arrow(center, cc, cell * 0.6);
+        for (path, width) in &[(&dp_arrow, 2.0), (&cc_arrow, 1.5)] {
+            ctx.stroke(*path, &HALO, width + 2.0);
+            ctx.stroke(*path, &EXIT, *width);
+        }
+
+        let (label, anchor) = match (transition.entry, transition.op) {
+            (Some(entry), Some(op)) => (format!("{:?}", op), self.codel_rect(entry).center()),
+            _ => ("Restricted".to_string(), center),
+        };
+        let layout = ctx
+            .text()
+            .new_text_layout(label)
+            .font(FontFamily::SYSTEM_UI, 12.0)
+            .text_color(NEXT_BLOCK)
+            .build()
+            .unwrap();
+        let origin = anchor + dp * cell + Vec2::new(4.0, 4.0);
+        let background = Rect::from_origin_size(origin, layout.size()).inset(3.0);
+        ctx.fill(background.to_rounded_rect(3.0), &HALO);
+        ctx.draw_text(&layout, origin);
+    }
+}
+
+/// The codels on a straight line between two codels, excluding `from`
+fn line(from: Codel, to: Codel) -> impl Iterator<Item = Codel> {
+    let (dx, dy) = (to.x as f64 - from.x as f64, to.y as f64 - from.y as f64);
+    let steps = dx.abs().max(dy.abs()) as u32;
+    (1..=steps).map(move |i| {
+        let t = i as f64 / steps as f64;
+        Codel::new(
+            (from.x as f64 + dx * t).round() as u32,
+            (from.y as f64 + dy * t).round() as u32,
+        )
+    })
+}
+
+/// A unit vector pointing the way of the DP
+fn direction(dp: DirectionPointer) -> Vec2 {
+    match dp {
+        DirectionPointer::Up => Vec2::new(0.0, -1.0),
+        DirectionPointer::Down => Vec2::new(0.0, 1.0),
+        DirectionPointer::Left => Vec2::new(-1.0, 0.0),
+        DirectionPointer::Right => Vec2::new(1.0, 0.0),
+    }
+}
+
+fn arrow(from: Point, dir: Vec2, length: f64) -> BezPath {
+    let tip = from + dir * length;
+    let back = -dir * length * 0.35;
+    let side = Vec2::new(-dir.y, dir.x) * length * 0.25;
+    let mut path = BezPath::new();
+    path.move_to(from);
+    path.line_to(tip);
+    path.move_to(tip + back + side);
+    path.line_to(tip);
+    path.line_to(tip + back - side);
+    path
+}
+
+/// Add or remove a breakpoint on the whole block containing `codel`
+fn toggle_breakpoint(data: &mut AppData, codel: Codel) {
+    let block = data.env.image.block(codel);
+    if data.breakpoints.contains(&codel) {
+        for codel in &block.codels {
+            data.breakpoints.remove(codel);
+        }
+    } else {
+        data.breakpoints.extend(block.codels.iter().copied());
+    }
 }
 
 impl Widget<AppData> for PietViewWidget {
@@ -14,21 +322,107 @@ impl Widget<AppData> for PietViewWidget {
             Event::WindowConnected => {
                 ctx.request_paint();
             }
+            Event::Command(cmd) if cmd.is(UNHOVER) => {
+                data.hover = None;
+            }
+            Event::Command(cmd) if cmd.is(FIT_TO_WINDOW) => {
+                self.fit = true;
+                self.fit_to(ctx.size(), &data.env.image);
+                ctx.request_paint();
+            }
+            Event::Wheel(mouse) => {
+                self.zoom_around(mouse.pos, (-mouse.wheel_delta.y / 400.0).exp());
+                ctx.request_paint();
+                ctx.set_handled();
+            }
+            Event::MouseDown(mouse) => {
+                let codel = self.codel_at(mouse.pos, data);
+                self.gesture = match (data.drawing && mouse.button.is_left(), codel) {
+                    (true, Some(codel)) => match data.tool {
+                        Tool::Pencil => {
+                            data.begin_stroke();
+                            data.apply_tool(codel);
+                            Some(Gesture::Paint(codel))
+                        }
+                        Tool::Fill => {
+                            data.apply_tool(codel);
+                            None
+                        }
+                    },
+                    (true, None) => None,
+                    (false, _) => Some(Gesture::Press(mouse.pos)),
+                };
+                ctx.set_active(self.gesture.is_some());
+                ctx.request_paint();
+            }
+            Event::MouseMove(mouse) if !ctx.is_active() => {
+                let hover = self.codel_at(mouse.pos, data);
+                if hover != data.hover {
+                    data.hover = hover;
+                    ctx.request_paint();
+                }
+            }
+            Event::MouseMove(mouse) => match self.gesture {
+                Some(Gesture::Press(start)) if (mouse.pos - start).hypot() > DRAG_THRESHOLD => {
+                    self.origin += mouse.pos - start;
+                    self.fit = false;
+                    self.gesture = Some(Gesture::Pan(mouse.pos));
+                    ctx.request_paint();
+                }
+                Some(Gesture::Pan(last)) => {
+                    self.origin += mouse.pos - last;
+                    self.gesture = Some(Gesture::Pan(mouse.pos));
+                    ctx.request_paint();
+                }
+                Some(Gesture::Paint(last)) => {
+                    if let Some(codel) = self.codel_at(mouse.pos, data) {
+                        for codel in line(last, codel) {
+                            data.apply_tool(codel);
+                        }
+                        self.gesture = Some(Gesture::Paint(codel));
+                        ctx.request_paint();
+                    }
+                }
+                _ => (),
+            },
+            Event::MouseUp(mouse) if ctx.is_active() => {
+                ctx.set_active(false);
+                data.end_stroke();
+                if let Some(Gesture::Press(_)) = self.gesture.take() {
+                    if let Some(codel) = self.codel_at(mouse.pos, data) {
+                        toggle_breakpoint(data, codel);
+                        ctx.request_paint();
+                    }
+                }
+            }
             _ => (), //  log!("{:?}", &event),
         };
     }
 
     fn lifecycle(
         &mut self,
-        _ctx: &mut LifeCycleCtx,
-        _event: &LifeCycle,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
         _data: &AppData,
         _env: &Env,
     ) {
+        // the data can't be changed from here
+        if let LifeCycle::HotChanged(false) = event {
+            ctx.submit_command(UNHOVER.to(ctx.widget_id()));
+        }
     }
 
     fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppData, data: &AppData, _env: &Env) {
-        if data.env != old_data.env {
+        if !data.env.image.same(&old_data.env.image) && self.fit {
+            self.fit_to(ctx.size(), &data.env.image);
+        }
+        if data.follow && (data.env.cp != old_data.env.cp || !old_data.follow) {
+            self.follow(ctx.size(), data.env.cp);
+        }
+        if data.env != old_data.env
+            || !data.breakpoints.same(&old_data.breakpoints)
+            || data.hover != old_data.hover
+        {
             ctx.request_paint();
         }
     }
@@ -37,48 +431,77 @@ impl Widget<AppData> for PietViewWidget {
         &mut self,
         _layout_ctx: &mut LayoutCtx,
         bc: &BoxConstraints,
-        _data: &AppData,
+        data: &AppData,
         _env: &Env,
     ) -> Size {
-        let max_size = bc.max();
-        let min_side = max_size.height.min(max_size.width);
-        Size {
-            width: min_side,
-            height: min_side,
+        let max = bc.max();
+        let size = bc.constrain(Size::new(
+            if max.width.is_finite() {
+                max.width
+            } else {
+                400.0
+            },
+            if max.height.is_finite() {
+                max.height
+            } else {
+                400.0
+            },
+        ));
+        if self.fit {
+            self.fit_to(size, &data.env.image);
         }
+        size
     }
 
     fn paint(&mut self, ctx: &mut PaintCtx, data: &AppData, _env: &Env) {
-        let size: Size = ctx.size();
-        let w0 = (size.width as u32 / data.env.image.png_info.width) as f64;
-        let h0 = (size.height as u32 / data.env.image.png_info.height) as f64;
-        let cell_size = Size {
-            width: w0,
-            height: h0,
-        };
-        self.cell_size = cell_size;
-        for col in 0..data.env.image.png_info.width {
-            for row in 0..data.env.image.png_info.height {
-                let point = Point {
-                    x: w0 * col as f64,
-                    y: h0 * row as f64,
-                };
-                let codel = crate::ty::Codel::new(col, row);
-                let rect = Rect::from_origin_size(point, cell_size);
-                let color_raw = &data.env.image[codel];
-                let color = Color::rgb8(color_raw[0], color_raw[1], color_raw[2]);
-                ctx.fill(rect, &color);
+        let image = &data.env.image;
+        let view = ctx.size().to_rect();
+        ctx.clip(view);
+
+        if !matches!(&self.bitmap, Some((cached, _)) if cached.same(image)) {
+            let bitmap = ctx
+                .make_image(
+                    image.png_info.width as usize,
+                    image.png_info.height as usize,
+                    image.bytes(),
+                    ImageFormat::Rgb,
+                )
+                .unwrap();
+            self.bitmap = Some((image.clone(), bitmap));
+        }
+        if let Some((_, bitmap)) = &self.bitmap {
+            ctx.draw_image(
+                bitmap,
+                self.image_rect(image),
+                InterpolationMode::NearestNeighbor,
+            );
+        }
+
+        if data.dim_dead {
+            self.paint_dead(ctx, image, &data.env.dialect);
+        }
+
+        for codel in data.breakpoints.iter() {
+            let rect = self.codel_rect(*codel);
+            if rect.intersect(view).area() > 0.0 {
+                ctx.stroke(rect.inset(-1.0), &BREAKPOINT, 2.0);
             }
         }
 
-        let point = Point {
-            x: (w0 as u32 * data.env.cp.x) as f64 + (w0 as u32 / 2) as f64,
-            y: (h0 as u32 * data.env.cp.y) as f64 + (h0 as u32 / 2) as f64,
-        };
-        let radius = (w0 + h0) / 2.0;
+        if let Some(hover) = data.hover.filter(|hover| image.contains(*hover)) {
+            let block = data.env.image.block(hover);
+            ctx.stroke(self.block_outline(&block.codels), &HOVER, 1.0);
+        }
+
+        let point = self.codel_rect(data.env.cp).center();
+        let radius = self.zoom;
         let circle = druid::piet::kurbo::Circle::new(point, radius * 0.4);
         ctx.fill(circle, &Color::rgb8(0x48, 0x1e, 0x40));
         let inner_circle = druid::piet::kurbo::Circle::new(point, radius * 0.2);
         ctx.fill(inner_circle, &Color::rgb8(0xff, 0xff, 0xff));
+
+        if data.env.state != ExecState::Halted {
+            self.paint_transition(ctx, data);
+        }
     }
 }
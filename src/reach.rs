@@ -0,0 +1,179 @@
+use crate::dialect::{Dialect, White};
+use crate::disasm::{disassemble, Outcome};
+use crate::env::PietEnv;
+use crate::image::PietImg;
+use crate::ty::*;
+use std::collections::{HashSet, VecDeque};
+
+/// The part a codel plays in running its program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// In a colour block the program goes through whatever pointers and switches pop, or white
+    /// or black it can slide across or be restricted by
+    Live,
+    /// In a colour block only reached after a pointer or switch turns the DP or CC, so whether
+    /// it runs depends on the values popped
+    DataDependent,
+    /// In a colour block nothing ever goes into
+    Unreachable,
+    /// White or black that nothing slides across or is restricted by
+    Decorative,
+}
+
+/// Which parts of a program can ever run, found without running it
+pub struct Reach {
+    width: u32,
+    /// The role of each codel, row by row
+    roles: Vec<Role>,
+    /// The first codel, colour and size of each colour block that's never reached
+    pub unreachable: Vec<(Codel, PietColor, usize)>,
+    /// The same for each colour block only reached after a pointer or switch turns
+    pub data_dependent: Vec<(Codel, PietColor, usize)>,
+    /// How many codels are decorative
+    pub decorative: usize,
+}
+
+impl Reach {
+    /// Walk every DP and CC the program could be in from its first codel, once letting pointers
+    /// and switches turn any way and once never letting them turn, moving into white the way
+    /// `dialect` does
+    pub fn new(image: &PietImg, dialect: &Dialect) -> Self {
+        let disassembly = disassemble(image, dialect);
+        let reached: HashSet<usize> = disassembly
+            .blocks
+            .iter()
+            .map(|block| image.block_id(block.codel))
+            .collect();
+
+        // with pointers and switches leaving the DP and CC as they are
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        if !disassembly.blocks.is_empty() {
+            queue.push_back((0, DirectionPointer::Right, CodelChoser::Left));
+        }
+        while let Some((index, dp, cc)) = queue.pop_front() {
+            if !seen.insert((index, dp, cc)) {
+                continue;
+            }
+            let outcome = disassembly.blocks[index]
+                .exits
+                .iter()
+                .find(|(d, c, _)| (*d, *c) == (dp, cc))
+                .map(|(_, _, outcome)| outcome);
+            match outcome {
+                Some(Outcome::Move { target, dp, cc, .. }) => queue.push_back((*target, *dp, *cc)),
+                Some(Outcome::Blocked) => {
+                    queue.push_back((index, dp, cc.toggled()));
+                    queue.push_back((index, dp.clockwise(), cc));
+                }
+                Some(Outcome::Trapped) | None => {}
+            }
+        }
+        let unturned: HashSet<usize> = seen
+            .iter()
+            .map(|(index, _, _)| image.block_id(disassembly.blocks[*index].codel))
+            .collect();
+
+        // white slid across and black run into around every way out of every block reached,
+        // white that's stopped in is reached like any other block
+        let mut env = PietEnv::new(image.clone(), *dialect);
+        let mut used = HashSet::new();
+        for block in &disassembly.blocks {
+            for (dp, cc, _) in &block.exits {
+                env.cp = block.codel;
+                env.dp = *dp;
+                env.cc = *cc;
+                let exit = env.next_transition().exit;
+                let Some(front) = exit.block_in_dir(*dp).filter(|c| image.contains(*c)) else {
+                    continue;
+                };
+                match image.color(front) {
+                    PietColor::White if dialect.white == White::Slide => {
+                        env.slide_path(front, |codel| {
+                            used.insert(codel);
+                        });
+                    }
+                    PietColor::Black => {
+                        used.insert(front);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let (width, height) = (image.png_info.width, image.png_info.height);
+        let mut roles = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let codel = Codel::new(x, y);
+                let id = image.block_id(codel);
+                roles.push(match image.color(codel) {
+                    PietColor::White | PietColor::Black if used.contains(&codel) => Role::Live,
+                    _ if unturned.contains(&id) => Role::Live,
+                    _ if reached.contains(&id) => Role::DataDependent,
+                    PietColor::White | PietColor::Black => Role::Decorative,
+                    _ => Role::Unreachable,
+                });
+            }
+        }
+
+        let mut reach = Reach {
+            width,
+            roles,
+            unreachable: vec![],
+            data_dependent: vec![],
+            decorative: 0,
+        };
+        reach.decorative = reach
+            .roles
+            .iter()
+            .filter(|role| **role == Role::Decorative)
+            .count();
+        for block in &image.blocks().blocks {
+            let codel = block.codels[0];
+            let found = (codel, image.color(codel), block.codels.len());
+            match reach.role(codel) {
+                Role::Unreachable => reach.unreachable.push(found),
+                Role::DataDependent => reach.data_dependent.push(found),
+                _ => {}
+            }
+        }
+        reach
+    }
+
+    pub fn role(&self, codel: Codel) -> Role {
+        self.roles[(codel.y * self.width + codel.x) as usize]
+    }
+}
+
+impl std::fmt::Display for Reach {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        for (blocks, what) in [
+            (&self.unreachable, "never reached"),
+            (
+                &self.data_dependent,
+                "only reached after a pointer or switch turns",
+            ),
+        ] {
+            writeln!(f, "{} block{} {}", blocks.len(), plural(blocks.len()), what)?;
+            for (codel, color, size) in blocks {
+                writeln!(
+                    f,
+                    "    at ({}, {}): {:?}, {} codel{}",
+                    codel.x,
+                    codel.y,
+                    color,
+                    size,
+                    plural(*size)
+                )?;
+            }
+        }
+        writeln!(
+            f,
+            "{} decorative codel{}",
+            self.decorative,
+            plural(self.decorative)
+        )
+    }
+}
@@ -0,0 +1,182 @@
+use crate::cfg::Cfg;
+use crate::dialect::Dialect;
+use crate::disasm::disassemble;
+use crate::env::ExecState;
+use crate::image::PietImg;
+use crate::ir::{Ir, IrEnv};
+use crate::looping::LoopDetector;
+use crate::AppData;
+use druid::widget::Controller;
+use druid::{Data, Env, Event, EventCtx, TimerToken, Widget};
+use instant::{Duration, Instant};
+
+/// How often we tick when running at full speed, about once a frame
+const FRAME: Duration = Duration::from_millis(16);
+/// How long a full speed tick may keep stepping before handing control back to the UI
+const FRAME_BUDGET: Duration = Duration::from_millis(10);
+/// Steps taken between checks of the frame budget
+const STEP_BATCH: usize = 64;
+
+/// Drives `PietEnv::step` off a timer while `AppData::running` is set, running on the IR
+/// instead at full speed when there are no breakpoints or loops to stop on
+#[derive(Default)]
+pub struct Runner {
+    timer: Option<TimerToken>,
+    /// The IR of the program last run on it, and that program
+    ir: Option<(PietImg, Ir)>,
+    /// Watches every step taken while `AppData::detect_loops` is set
+    loops: LoopDetector,
+}
+
+/// The IR for the next tick, if it's to be run on or checked against, built from `data`'s
+/// program unless `built` already is
+fn ir<'a>(built: &'a mut Option<(PietImg, Ir)>, data: &AppData) -> Option<&'a Ir> {
+    let on_ir = data.full_speed && data.breakpoints.is_empty() && !data.detect_loops;
+    if !on_ir && !data.check_ir {
+        return None;
+    }
+    let image = &data.env.image;
+    if !matches!(built, Some((from, _)) if from.same(image)) {
+        // the IR only runs dialects that slide across white, which all disassemble the same
+        let ir = Ir::new(&Cfg::new(&disassemble(image, &Dialect::SPEC)));
+        *built = Some((image.clone(), ir));
+    }
+    built.as_ref().map(|(_, ir)| ir)
+}
+
+/// What each step taken in a tick is checked with
+struct Checks<'a> {
+    ir: Option<IrEnv<'a>>,
+    loops: Option<&'a mut LoopDetector>,
+}
+
+impl AppData {
+    /// Take a single step, returning whether execution may carry on
+    pub fn step(&mut self) -> bool {
+        let cp = self.env.cp;
+        self.env.step();
+        match self.env.state {
+            // don't trip over a breakpoint we're still sitting on after a flow restriction
+            ExecState::Running => !(self.env.cp != cp && self.breakpoints.contains(&self.env.cp)),
+            ExecState::WaitingForInput => true,
+            ExecState::Halted | ExecState::Error(_) => false,
+        }
+    }
+
+    /// Take a single step, and the same step on the IR when checking it, stopping with an error
+    /// if they've ended up differently or the program's stuck in a loop
+    fn step_checked(&mut self, checks: &mut Checks) -> bool {
+        let running = self.step();
+        if let Some(ir) = &mut checks.ir {
+            ir.step();
+            if let Err(difference) = ir.compare(&self.env) {
+                self.env.state = ExecState::Error(difference);
+                return false;
+            }
+        }
+        if let Some(found) = checks
+            .loops
+            .as_mut()
+            .and_then(|loops| loops.observe(&self.env))
+        {
+            self.env.state = ExecState::Error(found.to_string());
+            return false;
+        }
+        running
+    }
+
+    /// Run for one timer tick, pausing if the program stopped
+    fn tick(&mut self, ir: Option<&Ir>, loops: &mut LoopDetector) {
+        let mut checks = Checks {
+            ir: ir
+                .filter(|_| self.check_ir)
+                .and_then(|ir| IrEnv::resume(ir, self.env.clone())),
+            loops: Some(loops).filter(|_| self.detect_loops),
+        };
+        if !self.full_speed {
+            self.running = self.step_checked(&mut checks);
+            return;
+        }
+        let on_ir = !self.check_ir && !self.detect_loops && self.breakpoints.is_empty();
+        if let Some(ir) = ir.filter(|_| on_ir) {
+            if let Some(on_ir) = IrEnv::resume(ir, self.env.clone()) {
+                self.tick_on_ir(on_ir);
+                return;
+            }
+        }
+
+        let start = Instant::now();
+        loop {
+            for _ in 0..STEP_BATCH {
+                if !self.step_checked(&mut checks) {
+                    self.running = false;
+                    return;
+                }
+                // idle until the next tick rather than spinning on an empty input
+                if self.env.state == ExecState::WaitingForInput {
+                    return;
+                }
+            }
+            if start.elapsed() >= FRAME_BUDGET {
+                return;
+            }
+        }
+    }
+
+    /// Run a full speed tick on the IR, picking up where it leaves off when it's done
+    fn tick_on_ir(&mut self, mut on_ir: IrEnv) {
+        let start = Instant::now();
+        while start.elapsed() < FRAME_BUDGET {
+            for _ in 0..STEP_BATCH {
+                on_ir.step();
+                match on_ir.env.state {
+                    ExecState::Running => {}
+                    ExecState::WaitingForInput => {
+                        self.env = on_ir.into_env();
+                        return;
+                    }
+                    ExecState::Halted | ExecState::Error(_) => {
+                        self.running = false;
+                        self.env = on_ir.into_env();
+                        return;
+                    }
+                }
+            }
+        }
+        self.env = on_ir.into_env();
+    }
+
+    fn tick_interval(&self) -> Duration {
+        if self.full_speed {
+            FRAME
+        } else {
+            Duration::from_secs_f64(1.0 / self.speed)
+        }
+    }
+}
+
+impl<W: Widget<AppData>> Controller<AppData, W> for Runner {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Timer(token) if Some(*token) == self.timer => {
+                self.timer = None;
+                if data.running {
+                    let ir = ir(&mut self.ir, data);
+                    data.tick(ir, &mut self.loops);
+                }
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+
+        if data.running && self.timer.is_none() {
+            self.timer = Some(ctx.request_timer(data.tick_interval()));
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use crate::env::PietOp;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Commands with the size of the block each is executed from, the size only matters for push
+pub type Plan = Vec<(PietOp, u32)>;
+
+/// What a synthesised constant is made as small as possible in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    /// Codels, every pushed block plus one for each other command
+    Area,
+    /// Commands, with no block pushed bigger than the given size
+    Commands(u32),
+}
+
+/// Values below this are found by trying every short enough sequence of commands
+const SEARCHED: i64 = 64;
+/// The most values on the stack at once in that search
+const DEPTH: usize = 4;
+/// How far from zero any value on the stack can get in that search
+const BOUND: i64 = 2 * SEARCHED;
+/// Below this, values close by are looked further for
+const TABLE_SIZE: i64 = 256;
+/// How far either side of a product is searched for one that's cheap to make
+const OFFSETS: i64 = 16;
+/// From here on, values are only made from their quotient by one of `DIVISORS` and what's left
+/// over, as there are too many below them to look through
+const LARGE: i64 = TABLE_SIZE * TABLE_SIZE;
+/// Divisors with no prime factors but 2 and 3, so quotients of quotients come out the same
+/// whichever order they're divided in and few of them are looked at
+const DIVISORS: [i64; 12] = [2, 3, 4, 6, 8, 9, 12, 16, 18, 24, 27, 32];
+
+/// A piece of a way of making a value
+#[derive(Debug, Clone, Copy)]
+enum Part {
+    /// A value made its own cheapest way
+    Value(i64),
+    Push(i64),
+    Op(PietOp),
+}
+
+/// Values with what they cost and the parts that make them
+type Found = HashMap<i64, (u64, Vec<Part>)>;
+/// Stacks with the cheapest way found to them, as what it costs and the stack and part it's
+/// reached from
+type Ways = HashMap<Vec<i64>, (u64, Option<(Vec<i64>, Part)>)>;
+
+/// The cheapest way in `metric` of making every value below `SEARCHED`, searched for once
+fn table(metric: Metric) -> Found {
+    static TABLES: OnceLock<Mutex<HashMap<Metric, Found>>> = OnceLock::new();
+    let mut tables = TABLES.get_or_init(Default::default).lock().unwrap();
+    tables
+        .entry(metric)
+        .or_insert_with(|| search(metric))
+        .clone()
+}
+
+/// Tries every sequence of push, dup, add, subtract, multiply and roll, cheapest first, that
+/// keeps no more than `DEPTH` values within `BOUND` of zero on the stack, until each value
+/// below `SEARCHED` has been left alone on it
+fn search(metric: Metric) -> Found {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    // pushing anything bigger costs more area than pushing the value wanted
+    let largest = match metric {
+        Metric::Area => SEARCHED - 1,
+        Metric::Commands(largest) => (largest as i64).min(BOUND),
+    };
+    // each push after the first costs this much more than the one before
+    let step = match metric {
+        Metric::Area => 1,
+        Metric::Commands(_) => 0,
+    };
+    let mut best: Ways = HashMap::new();
+    let mut found = HashMap::new();
+    // stacks reached, or stacks with the value to push onto them next, so that bigger pushes
+    // are only tried once they're the cheapest thing left to try
+    let mut queue = BinaryHeap::new();
+    best.insert(vec![], (0, None));
+    queue.push(Reverse((0, vec![], None)));
+    while let Some(Reverse((cost, stack, push))) = queue.pop() {
+        let mut next = vec![];
+        if let Some(v) = push {
+            next.push((Part::Push(v), [&stack[..], &[v]].concat()));
+            if v < largest {
+                queue.push(Reverse((cost + step, stack.clone(), Some(v + 1))));
+            }
+        } else {
+            if best[&stack].0 < cost {
+                continue;
+            }
+            if let [n] = stack[..] {
+                if (0..SEARCHED).contains(&n) && !found.contains_key(&n) {
+                    found.insert(n, (cost, path(&best, &stack)));
+                    if found.len() == SEARCHED as usize {
+                        break;
+                    }
+                }
+            }
+            if stack.len() < DEPTH && largest > 0 {
+                queue.push(Reverse((cost + 1, stack.clone(), Some(1))));
+            }
+            next = steps(&stack);
+        }
+        // a push is already costed by its place in the queue
+        let cost = if push.is_some() { cost } else { cost + 1 };
+        for (part, next) in next {
+            if next.iter().any(|v| v.abs() > BOUND) {
+                continue;
+            }
+            if best.get(&next).is_none_or(|(best, _)| cost < *best) {
+                best.insert(next.clone(), (cost, Some((stack.clone(), part))));
+                queue.push(Reverse((cost, next, None)));
+            }
+        }
+    }
+    found
+}
+
+/// Every command other than push that does something to `stack`, with the stack it leaves
+fn steps(stack: &[i64]) -> Vec<(Part, Vec<i64>)> {
+    use PietOp::*;
+    let mut steps = vec![];
+    if let Some(&top) = stack.last() {
+        steps.push((Part::Op(Duplicate), [stack, &[top][..]].concat()));
+    }
+    if let [rest @ .., a, b] = stack {
+        for (op, v) in [(Add, a + b), (Subtract, a - b), (Multiply, a * b)] {
+            steps.push((Part::Op(op), [rest, &[v][..]].concat()));
+        }
+        if *a >= 0 && *a as usize <= rest.len() {
+            let mut rolled = rest.to_vec();
+            let start = rest.len() - *a as usize;
+            if *a > 0 {
+                rolled[start..].rotate_right(b.rem_euclid(*a) as usize);
+            }
+            steps.push((Part::Op(Roll), rolled));
+        }
+    }
+    steps
+}
+
+/// The steps taken to get to `stack`, from the way to each stack `search` found
+fn path(best: &Ways, stack: &[i64]) -> Vec<Part> {
+    let mut parts = vec![];
+    let mut at = stack.to_vec();
+    while let Some((from, part)) = &best[&at].1 {
+        parts.push(*part);
+        at = from.clone();
+    }
+    parts.reverse();
+    parts
+}
+
+/// Finds short sequences of push, dup, add, subtract, multiply and roll that leave a value on
+/// the stack, remembering everything it's found.
+///
+/// Values below `SEARCHED` are the cheapest there are, short of ones that need a deeper stack
+/// or bigger values on it than `search` keeps to. Bigger values are only approximately the
+/// cheapest: they're built up from smaller ones by pushing the value whole, adding or
+/// multiplying two values (making the second from a dup of the first when that's cheaper, so
+/// doubling and squaring come out of it), and the same near the value with the difference
+/// added or subtracted.
+pub struct Synth {
+    metric: Metric,
+    cache: Found,
+    /// The best of `combined` for a value and limit
+    combined: HashMap<(i64, i64), Option<Vec<Part>>>,
+}
+
+impl Synth {
+    pub fn new(metric: Metric) -> Self {
+        Synth {
+            metric,
+            cache: table(metric),
+            combined: HashMap::new(),
+        }
+    }
+
+    /// Commands that push `n` onto the stack and leave everything under it alone
+    pub fn plan(&mut self, n: i64) -> Plan {
+        let (_, parts) = self.best(n);
+        let mut plan = vec![];
+        for part in parts {
+            match part {
+                Part::Value(v) => plan.extend(self.plan(v)),
+                Part::Push(v) => plan.push((PietOp::Push, v as u32)),
+                Part::Op(op) => plan.push((op, 1)),
+            }
+        }
+        plan
+    }
+
+    /// What `parts` cost, finding the cheapest way of making each value in them
+    fn cost_of(&mut self, parts: &[Part]) -> u64 {
+        parts
+            .iter()
+            .map(|part| match *part {
+                Part::Value(v) => self.best(v).0,
+                Part::Push(v) => match self.metric {
+                    Metric::Area => v as u64,
+                    Metric::Commands(_) => 1,
+                },
+                Part::Op(_) => 1,
+            })
+            .sum()
+    }
+
+    fn best(&mut self, n: i64) -> (u64, Vec<Part>) {
+        if let Some(found) = self.cache.get(&n) {
+            return found.clone();
+        }
+        use Part::*;
+        let mut options: Vec<Vec<Part>> = vec![];
+        if n <= 0 {
+            match 1i64.checked_sub(n) {
+                // 1 - (1 - n)
+                Some(m) => options.push(vec![Value(1), Value(m), Op(PietOp::Subtract)]),
+                // the smallest value has no opposite, so is one less than the next
+                None => options.push(vec![Value(n + 1), Value(1), Op(PietOp::Subtract)]),
+            }
+        } else if n >= LARGE {
+            if push_allowed(self.metric, n) {
+                options.push(vec![Push(n)]);
+            }
+            for a in DIVISORS {
+                let (q, r) = (n / a, n % a);
+                let mut parts = vec![Value(q), Value(a), Op(PietOp::Multiply)];
+                if r > 0 {
+                    parts.extend([Value(r), Op(PietOp::Add)]);
+                    // or from the next multiple up, if that's cheaper to get to
+                    if (q + 1).checked_mul(a).is_some() {
+                        options.push(vec![
+                            Value(q + 1),
+                            Value(a),
+                            Op(PietOp::Multiply),
+                            Value(a - r),
+                            Op(PietOp::Subtract),
+                        ]);
+                    }
+                }
+                options.push(parts);
+            }
+        } else {
+            if push_allowed(self.metric, n) {
+                options.push(vec![Push(n)]);
+            }
+            options.extend(self.combined(n, n));
+            // big values are close enough to cheap ones without looking as far
+            let offsets = if n < TABLE_SIZE { OFFSETS } else { OFFSETS / 4 };
+            for r in 1..=offsets.min(n - 1) {
+                if let Some(combined) = self.combined(n - r, n) {
+                    options.push([combined, vec![Value(r), Op(PietOp::Add)]].concat());
+                }
+                if let Some(combined) = self.combined(n + r, n) {
+                    options.push([combined, vec![Value(r), Op(PietOp::Subtract)]].concat());
+                }
+            }
+        }
+        let mut best: Option<(u64, Vec<Part>)> = None;
+        for parts in options {
+            let cost = self.cost_of(&parts);
+            if best.as_ref().is_none_or(|(best, _)| cost < *best) {
+                best = Some((cost, parts));
+            }
+        }
+        let best = best.unwrap_or_else(|| (n as u64, vec![Push(n)]));
+        self.cache.insert(n, best.clone());
+        best
+    }
+
+    /// The cheapest way of making `n` by adding or multiplying two values, where the second is
+    /// either made on its own or from a copy of the first, using only values below `limit`
+    fn combined(&mut self, n: i64, limit: i64) -> Option<Vec<Part>> {
+        // every value used is below `n` anyway, and below `limit` only matters for small ones
+        let key = (n, limit.min(n));
+        if let Some(found) = self.combined.get(&key) {
+            return found.clone();
+        }
+        use Part::*;
+        let mut pairs = vec![];
+        let mut a = 2;
+        while a * a <= n {
+            if n % a == 0 {
+                pairs.push((a, n / a, PietOp::Multiply));
+                pairs.push((n / a, a, PietOp::Multiply));
+            }
+            a += 1;
+        }
+        // sums where the second value is made from the first, one of the ways `derived` knows,
+        // only the closest to halves for big values to keep from searching too widely
+        let (near, multiples): (i64, &[i64]) = if n < TABLE_SIZE {
+            (OFFSETS, &[2, 3])
+        } else {
+            (1, &[2])
+        };
+        for k in -near..=near {
+            for &times in multiples {
+                if (n - k) % times == 0 {
+                    let a = (n - k) / times;
+                    pairs.push((a, n - a, PietOp::Add));
+                }
+            }
+        }
+        let root = (n as f64).sqrt() as i64;
+        pairs.push((root, n - root, PietOp::Add));
+
+        let mut best: Option<(u64, Vec<Part>)> = None;
+        for (a, b, op) in pairs {
+            if a <= 0 || b <= 0 || a >= limit {
+                continue;
+            }
+            let mut options = derived(a, b, limit)
+                .into_iter()
+                .map(|derived| [vec![Value(a)], derived, vec![Op(op)]].concat())
+                .collect::<Vec<_>>();
+            if b < limit && op == PietOp::Multiply {
+                options.push(vec![Value(a), Value(b), Op(op)]);
+            }
+            for parts in options {
+                let cost = self.cost_of(&parts);
+                if best.as_ref().is_none_or(|(best, _)| cost < *best) {
+                    best = Some((cost, parts));
+                }
+            }
+        }
+        let best = best.map(|(_, parts)| parts);
+        self.combined.insert(key, best.clone());
+        best
+    }
+}
+
+/// Ways that, with `a` on top of the stack, push `b` on top of it by working from a copy,
+/// using only values below `limit`
+fn derived(a: i64, b: i64, limit: i64) -> Vec<Vec<Part>> {
+    use Part::*;
+    use PietOp::*;
+    let mut options = vec![];
+    if b == a * a {
+        options.push(vec![Op(Duplicate), Op(Duplicate), Op(Multiply)]);
+    }
+    // from a, or from 2a made with another dup
+    for (base, start) in [
+        (a, vec![Op(Duplicate)]),
+        (2 * a, vec![Op(Duplicate), Op(Duplicate), Op(Add)]),
+    ] {
+        if b == base {
+            options.push(start.clone());
+        }
+        for (k, op) in [(b - base, Add), (base - b, Subtract)] {
+            if k > 0 && k <= OFFSETS && k < limit {
+                options.push([start.clone(), vec![Value(k), Op(op)]].concat());
+            }
+        }
+    }
+    if a > 1 && b % a == 0 {
+        let k = b / a;
+        if k > 2 && k <= OFFSETS && k < limit {
+            options.push(vec![Op(Duplicate), Value(k), Op(Multiply)]);
+        }
+    }
+    options
+}
+
+fn push_allowed(metric: Metric, n: i64) -> bool {
+    match metric {
+        Metric::Area => true,
+        Metric::Commands(largest) => n <= largest as i64,
+    }
+}
+
+/// How much `plan` costs in `metric`
+pub fn cost(metric: Metric, plan: &Plan) -> u64 {
+    match metric {
+        Metric::Area => plan
+            .iter()
+            .map(|(op, size)| match op {
+                PietOp::Push => *size as u64,
+                _ => 1,
+            })
+            .sum(),
+        Metric::Commands(_) => plan.len() as u64,
+    }
+}
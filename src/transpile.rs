@@ -0,0 +1,245 @@
+use crate::asm::mnemonic;
+use crate::cfg::Cfg;
+use crate::dialect::Dialect;
+use crate::disasm::{chooser, direction};
+use crate::env::PietOp;
+use std::fmt::Write;
+
+/// The stack and I/O the generated program runs on, each command behaving as it does in
+/// `PietEnv`
+const RUNTIME: &str = r#"#![allow(dead_code, unused_mut, unused_variables)]
+use std::io::{Read, Write};
+
+struct Machine {
+    stack: Vec<i64>,
+    input: String,
+    /// Bytes of the input read so far
+    read: usize,
+    out: std::io::BufWriter<std::io::Stdout>,
+}
+
+impl Machine {
+    fn new() -> Self {
+        let mut input = vec![];
+        std::io::stdin().read_to_end(&mut input).unwrap();
+        Machine {
+            stack: vec![],
+            input: String::from_utf8_lossy(&input).into_owned(),
+            read: 0,
+            out: std::io::BufWriter::new(std::io::stdout()),
+        }
+    }
+
+    fn binary(&mut self, f: impl Fn(i64, i64) -> Option<i64>) {
+        if let [.., a, b] = self.stack[..] {
+            if let Some(val) = f(a, b) {
+                self.stack.truncate(self.stack.len() - 2);
+                self.stack.push(val);
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<i64> {
+        self.stack.pop()
+    }
+
+    fn add(&mut self) {
+        self.binary(|a, b| Some(a.wrapping_add(b)))
+    }
+
+    fn sub(&mut self) {
+        self.binary(|a, b| Some(a.wrapping_sub(b)))
+    }
+
+    fn mul(&mut self) {
+        self.binary(|a, b| Some(a.wrapping_mul(b)))
+    }
+
+    fn div(&mut self) {
+        self.binary(|a, b| {
+            let (q, r) = (a.checked_div(b)?, a.checked_rem(b)?);
+            Some(if r != 0 && (r < 0) != (b < 0) { q - 1 } else { q })
+        })
+    }
+
+    fn rem(&mut self) {
+        self.binary(|a, b| {
+            let r = a.checked_rem(b)?;
+            Some(if r != 0 && (r < 0) != (b < 0) { r + b } else { r })
+        })
+    }
+
+    fn not(&mut self) {
+        if let Some(val) = self.stack.last_mut() {
+            *val = (*val == 0) as i64;
+        }
+    }
+
+    fn greater(&mut self) {
+        self.binary(|a, b| Some((a > b) as i64))
+    }
+
+    fn dup(&mut self) {
+        if let Some(&val) = self.stack.last() {
+            self.stack.push(val);
+        }
+    }
+
+    fn roll(&mut self) {
+        if let [.., depth, rolls] = self.stack[..] {
+            let len = self.stack.len() - 2;
+            if depth >= 0 && depth as usize <= len {
+                self.stack.truncate(len);
+                if depth > 0 {
+                    let start = len - depth as usize;
+                    self.stack[start..].rotate_right(rolls.rem_euclid(depth) as usize);
+                }
+            }
+        }
+    }
+
+    /// False once the input has run out
+    fn in_char(&mut self) -> bool {
+        match self.input[self.read..].chars().next() {
+            Some(c) => {
+                self.read += c.len_utf8();
+                self.stack.push(c as i64);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// False once there's nothing but whitespace left to read
+    fn in_number(&mut self) -> bool {
+        let rest = &self.input[self.read..];
+        let start = rest.len() - rest.trim_start().len();
+        if start == rest.len() {
+            self.read = self.input.len();
+            return false;
+        }
+        let end = rest[start..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |end| start + end);
+        if let Ok(val) = rest[start..end].parse() {
+            self.stack.push(val);
+        }
+        self.read += end;
+        true
+    }
+
+    fn out_number(&mut self) {
+        if let Some(val) = self.stack.pop() {
+            write!(self.out, "{}", val).unwrap();
+        }
+    }
+
+    fn out_char(&mut self) {
+        if let Some(val) = self.stack.pop() {
+            match u32::try_from(val).ok().and_then(char::from_u32) {
+                Some(c) => write!(self.out, "{}", c).unwrap(),
+                None => {
+                    self.out.flush().unwrap();
+                    eprintln!("Error: {:#X} is not a valid character", val);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+"#;
+
+/// Rust source for a program that runs the one `cfg` was made from, reading all of its input
+/// from stdin first. It stops where `PietEnv` would halt, wait for more input or stop on an
+/// error, with the same output.
+///
+/// Each state is an arm of a `match` that runs the command leaving it and picks the next
+/// state, from the popped value for pointers and switches and from how many times in a row
+/// the program's been blocked for blocked states. Commands run the way the spec says, so a
+/// program disassembled as any other dialect is refused.
+pub fn to_rust(cfg: &Cfg) -> Result<String, String> {
+    if *cfg.dialect() != Dialect::SPEC {
+        return Err("only programs run the way the spec says can be compiled".to_string());
+    }
+    let mut out = String::new();
+    writeln!(out, "// Generated by cornelis").unwrap();
+    out.push_str(RUNTIME);
+    writeln!(out).unwrap();
+    writeln!(out, "fn main() {{").unwrap();
+    writeln!(out, "    let mut m = Machine::new();").unwrap();
+    if !cfg.states.is_empty() {
+        writeln!(out, "    let mut state = 0;").unwrap();
+        writeln!(out, "    let mut restricted = 0;").unwrap();
+        writeln!(out, "    loop {{").unwrap();
+        writeln!(out, "        match state {{").unwrap();
+        for (id, edges) in cfg.edges_from().iter().enumerate() {
+            let state = cfg.states[id];
+            let block = cfg.block(id);
+            writeln!(
+                out,
+                "            // block {} at ({}, {}) dp {} cc {}",
+                state.block,
+                block.codel.x,
+                block.codel.y,
+                direction(state.dp),
+                chooser(state.cc)
+            )
+            .unwrap();
+            writeln!(out, "            {} => {{", id).unwrap();
+            let body = match edges[..] {
+                // trapped in white
+                [] => vec!["break;".to_string()],
+                [edge, _] if edge.command.is_none() => vec![
+                    "restricted += 1;".to_string(),
+                    "if restricted >= 8 {".to_string(),
+                    "    break;".to_string(),
+                    "}".to_string(),
+                    format!(
+                        "state = if restricted % 2 == 1 {{ {} }} else {{ {} }};",
+                        edges[0].to, edges[1].to
+                    ),
+                ],
+                [edge, ..] => {
+                    let mut body = vec!["restricted = 0;".to_string()];
+                    let next = edges.iter().map(|e| e.to.to_string()).collect::<Vec<_>>();
+                    match edge.command.unwrap() {
+                        PietOp::Pointer | PietOp::Switch => body.push(format!(
+                            "state = [{}][m.pop().map_or(0, |val| val.rem_euclid({})) as usize];",
+                            next.join(", "),
+                            next.len()
+                        )),
+                        op => {
+                            body.push(match op {
+                                PietOp::None => "// slide".to_string(),
+                                PietOp::Push => format!("m.stack.push({});", block.size),
+                                PietOp::Pop => "m.pop();".to_string(),
+                                PietOp::Mod => "m.rem();".to_string(),
+                                PietOp::Duplicate => "m.dup();".to_string(),
+                                PietOp::Greater => "m.greater();".to_string(),
+                                PietOp::InChar => "if !m.in_char() {\n    break;\n}".to_string(),
+                                PietOp::InNumber => {
+                                    "if !m.in_number() {\n    break;\n}".to_string()
+                                }
+                                PietOp::OutChar => "m.out_char();".to_string(),
+                                PietOp::OutNumber => "m.out_number();".to_string(),
+                                _ => format!("m.{}();", mnemonic(op)),
+                            });
+                            body.push(format!("state = {};", next[0]));
+                        }
+                    }
+                    body
+                }
+            };
+            for line in body.iter().flat_map(|line| line.lines()) {
+                writeln!(out, "                {}", line).unwrap();
+            }
+            writeln!(out, "            }}").unwrap();
+        }
+        writeln!(out, "            _ => unreachable!(),").unwrap();
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "    }}").unwrap();
+    }
+    writeln!(out, "    m.out.flush().unwrap();").unwrap();
+    writeln!(out, "}}").unwrap();
+    Ok(out)
+}
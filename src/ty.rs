@@ -1,4 +1,4 @@
-use druid::{Data, Lens};
+use druid::Data;
 use num_derive::FromPrimitive;
 
 #[derive(Data, Debug, Copy, Clone, PartialEq, FromPrimitive)]
@@ -52,6 +52,36 @@ impl PietColor {
             _ => panic!("not on the hue/light cycle!"),
         }
     }
+
+    /// The inverse of `get_color_scale`, wrapping hue and lightness around their cycles
+    pub fn from_color_scale(hue: u32, lightness: u32) -> Self {
+        const CYCLE: [[PietColor; 3]; 6] = [
+            [PietColor::LightRed, PietColor::Red, PietColor::DarkRed],
+            [
+                PietColor::LightYellow,
+                PietColor::Yellow,
+                PietColor::DarkYellow,
+            ],
+            [
+                PietColor::LightGreen,
+                PietColor::Green,
+                PietColor::DarkGreen,
+            ],
+            [PietColor::LightCyan, PietColor::Cyan, PietColor::DarkCyan],
+            [PietColor::LightBlue, PietColor::Blue, PietColor::DarkBlue],
+            [
+                PietColor::LightMagenta,
+                PietColor::Magenta,
+                PietColor::DarkMagenta,
+            ],
+        ];
+        CYCLE[(hue % 6) as usize][(lightness % 3) as usize]
+    }
+
+    pub fn rgb(&self) -> [u8; 3] {
+        let [_, r, g, b] = (*self as u32).to_be_bytes();
+        [r, g, b]
+    }
 }
 
 impl From<&[u8]> for PietColor {
@@ -62,12 +92,21 @@ impl From<&[u8]> for PietColor {
     }
 }
 
-#[derive(Data, Debug, PartialEq, Clone, Copy)]
+#[derive(Data, Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum CodelChoser {
     Left,
     Right,
 }
 
+impl CodelChoser {
+    pub fn toggled(self) -> Self {
+        match self {
+            CodelChoser::Left => CodelChoser::Right,
+            CodelChoser::Right => CodelChoser::Left,
+        }
+    }
+}
+
 impl std::fmt::Display for CodelChoser {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -78,13 +117,25 @@ impl std::fmt::Display for CodelChoser {
     }
 }
 
-#[derive(Data, Debug, PartialEq, Clone, Copy)]
+#[derive(Data, Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum DirectionPointer {
     Up,
     Down,
     Left,
     Right,
 }
+
+impl DirectionPointer {
+    pub fn clockwise(self) -> Self {
+        match self {
+            DirectionPointer::Right => DirectionPointer::Down,
+            DirectionPointer::Down => DirectionPointer::Left,
+            DirectionPointer::Left => DirectionPointer::Up,
+            DirectionPointer::Up => DirectionPointer::Right,
+        }
+    }
+}
+
 impl std::fmt::Display for DirectionPointer {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -97,7 +148,7 @@ impl std::fmt::Display for DirectionPointer {
     }
 }
 
-#[derive(Data, PartialEq, Debug, Clone, Copy)]
+#[derive(Data, PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub struct Codel {
     pub x: u32,
     pub y: u32,
@@ -0,0 +1,301 @@
+use crate::cfg::Cfg;
+use crate::dialect::Dialect;
+use crate::disasm::{chooser, direction};
+use crate::env::PietOp;
+use std::fmt::Write;
+
+/// The stack, kept in memory as 64 bit values from address 0 up, and the commands on it, each
+/// behaving as it does in `PietEnv`
+const RUNTIME: &str = r#"  (import "io" "out_number" (func $out_number (param i64)))
+  (import "io" "out_char" (func $out_char (param i32)))
+  ;; the next character, or -1 once the input has run out
+  (import "io" "in_char" (func $in_char (result i64)))
+  ;; 1 and a number, 2 if what's next isn't a number, or 0 once there's only whitespace left
+  (import "io" "in_number" (func $in_number (result i32 i64)))
+  (memory 1)
+  ;; number of values on the stack
+  (global $sp (mut i32) (i32.const 0))
+
+  (func $push (param $v i64)
+    (if (i32.ge_u (i32.mul (i32.add (global.get $sp) (i32.const 1)) (i32.const 8))
+                  (i32.mul (memory.size) (i32.const 65536)))
+      (then (drop (memory.grow (i32.const 1)))))
+    (i64.store (i32.mul (global.get $sp) (i32.const 8)) (local.get $v))
+    (global.set $sp (i32.add (global.get $sp) (i32.const 1))))
+
+  (func $pop (result i64)
+    (global.set $sp (i32.sub (global.get $sp) (i32.const 1)))
+    (i64.load (i32.mul (global.get $sp) (i32.const 8))))
+
+  ;; the value `i` down from the top
+  (func $peek (param $i i32) (result i64)
+    (i64.load (i32.mul (i32.sub (i32.sub (global.get $sp) (i32.const 1)) (local.get $i))
+                       (i32.const 8))))
+
+  (func $drop
+    (if (global.get $sp) (then (drop (call $pop)))))
+
+  (func $add (local $b i64)
+    (if (i32.lt_u (global.get $sp) (i32.const 2)) (then (return)))
+    (local.set $b (call $pop))
+    (call $push (i64.add (call $pop) (local.get $b))))
+
+  (func $sub (local $b i64)
+    (if (i32.lt_u (global.get $sp) (i32.const 2)) (then (return)))
+    (local.set $b (call $pop))
+    (call $push (i64.sub (call $pop) (local.get $b))))
+
+  (func $mul (local $b i64)
+    (if (i32.lt_u (global.get $sp) (i32.const 2)) (then (return)))
+    (local.set $b (call $pop))
+    (call $push (i64.mul (call $pop) (local.get $b))))
+
+  ;; true if `a` can't be divided by `b`
+  (func $undivisible (param $a i64) (param $b i64) (result i32)
+    (i32.or (i64.eqz (local.get $b))
+            (i32.and (i64.eq (local.get $a) (i64.const 0x8000000000000000))
+                     (i64.eq (local.get $b) (i64.const -1)))))
+
+  ;; floored
+  (func $div (local $a i64) (local $b i64) (local $q i64)
+    (if (i32.lt_u (global.get $sp) (i32.const 2)) (then (return)))
+    (local.set $b (call $peek (i32.const 0)))
+    (local.set $a (call $peek (i32.const 1)))
+    (if (call $undivisible (local.get $a) (local.get $b)) (then (return)))
+    (global.set $sp (i32.sub (global.get $sp) (i32.const 2)))
+    (local.set $q (i64.div_s (local.get $a) (local.get $b)))
+    (if (i32.and (i64.ne (i64.rem_s (local.get $a) (local.get $b)) (i64.const 0))
+                 (i32.ne (i64.lt_s (i64.rem_s (local.get $a) (local.get $b)) (i64.const 0))
+                         (i64.lt_s (local.get $b) (i64.const 0))))
+      (then (local.set $q (i64.sub (local.get $q) (i64.const 1)))))
+    (call $push (local.get $q)))
+
+  ;; the result has the same sign as the divisor
+  (func $mod (local $a i64) (local $b i64) (local $r i64)
+    (if (i32.lt_u (global.get $sp) (i32.const 2)) (then (return)))
+    (local.set $b (call $peek (i32.const 0)))
+    (local.set $a (call $peek (i32.const 1)))
+    (if (call $undivisible (local.get $a) (local.get $b)) (then (return)))
+    (global.set $sp (i32.sub (global.get $sp) (i32.const 2)))
+    (local.set $r (i64.rem_s (local.get $a) (local.get $b)))
+    (if (i32.and (i64.ne (local.get $r) (i64.const 0))
+                 (i32.ne (i64.lt_s (local.get $r) (i64.const 0))
+                         (i64.lt_s (local.get $b) (i64.const 0))))
+      (then (local.set $r (i64.add (local.get $r) (local.get $b)))))
+    (call $push (local.get $r)))
+
+  (func $not
+    (if (i32.eqz (global.get $sp)) (then (return)))
+    (call $push (i64.extend_i32_u (i64.eqz (call $pop)))))
+
+  (func $gt (local $b i64)
+    (if (i32.lt_u (global.get $sp) (i32.const 2)) (then (return)))
+    (local.set $b (call $pop))
+    (call $push (i64.extend_i32_u (i64.gt_s (call $pop) (local.get $b)))))
+
+  (func $dup
+    (if (global.get $sp) (then (call $push (call $peek (i32.const 0))))))
+
+  ;; reverse the values from `lo` up to but not including `hi`
+  (func $reverse (param $lo i32) (param $hi i32) (local $t i64)
+    (block $done
+      (loop $swap
+        (br_if $done (i32.ge_s (i32.add (local.get $lo) (i32.const 1)) (local.get $hi)))
+        (local.set $hi (i32.sub (local.get $hi) (i32.const 1)))
+        (local.set $t (i64.load (i32.mul (local.get $lo) (i32.const 8))))
+        (i64.store (i32.mul (local.get $lo) (i32.const 8))
+                   (i64.load (i32.mul (local.get $hi) (i32.const 8))))
+        (i64.store (i32.mul (local.get $hi) (i32.const 8)) (local.get $t))
+        (local.set $lo (i32.add (local.get $lo) (i32.const 1)))
+        (br $swap))))
+
+  (func $roll (local $depth i64) (local $rolls i64) (local $len i32) (local $start i32)
+              (local $split i32)
+    (if (i32.lt_u (global.get $sp) (i32.const 2)) (then (return)))
+    (local.set $rolls (call $peek (i32.const 0)))
+    (local.set $depth (call $peek (i32.const 1)))
+    (local.set $len (i32.sub (global.get $sp) (i32.const 2)))
+    ;; a negative depth, or one deeper than the stack, is ignored
+    (if (i32.or (i64.lt_s (local.get $depth) (i64.const 0))
+                (i64.gt_s (local.get $depth) (i64.extend_i32_u (local.get $len))))
+      (then (return)))
+    (global.set $sp (local.get $len))
+    (if (i64.eqz (local.get $depth)) (then (return)))
+    (local.set $start (i32.sub (local.get $len) (i32.wrap_i64 (local.get $depth))))
+    (local.set $split (i32.add (local.get $start) (i32.wrap_i64
+      (i64.rem_s (i64.add (i64.rem_s (local.get $rolls) (local.get $depth)) (local.get $depth))
+                 (local.get $depth)))))
+    (call $reverse (local.get $start) (local.get $len))
+    (call $reverse (local.get $start) (local.get $split))
+    (call $reverse (local.get $split) (local.get $len)))
+
+  ;; the popped value modulo `m`, 0 if there isn't one
+  (func $branch (param $m i64) (result i32)
+    (if (i32.eqz (global.get $sp)) (then (return (i32.const 0))))
+    (i32.wrap_i64 (i64.rem_s (i64.add (i64.rem_s (call $pop) (local.get $m)) (local.get $m))
+                             (local.get $m))))
+
+  (func $outnum
+    (if (global.get $sp) (then (call $out_number (call $pop)))))
+
+  ;; false if the value isn't a character, leaving it on the stack
+  (func $outchar (result i32) (local $v i64)
+    (if (i32.eqz (global.get $sp)) (then (return (i32.const 1))))
+    (local.set $v (call $peek (i32.const 0)))
+    (if (i32.or (i32.or (i64.lt_s (local.get $v) (i64.const 0))
+                        (i64.gt_s (local.get $v) (i64.const 0x10FFFF)))
+                (i32.and (i64.ge_s (local.get $v) (i64.const 0xD800))
+                         (i64.le_s (local.get $v) (i64.const 0xDFFF))))
+      (then (return (i32.const 0))))
+    (call $out_char (i32.wrap_i64 (call $pop)))
+    (i32.const 1))
+
+  ;; false once the input has run out
+  (func $inchar (result i32) (local $c i64)
+    (local.set $c (call $in_char))
+    (if (i64.lt_s (local.get $c) (i64.const 0)) (then (return (i32.const 0))))
+    (call $push (local.get $c))
+    (i32.const 1))
+
+  ;; false once there's only whitespace left to read
+  (func $innum (result i32) (local $status i32) (local $v i64)
+    (call $in_number)
+    (local.set $v)
+    (local.set $status)
+    (if (i32.eq (local.get $status) (i32.const 1)) (then (call $push (local.get $v))))
+    (i32.ne (local.get $status) (i32.const 0)))
+"#;
+
+/// What `run` gives back when the program halts
+pub const HALTED: i32 = 0;
+/// What `run` gives back when the program wants more input than there is
+pub const OUT_OF_INPUT: i32 = 1;
+/// What `run` gives back when the program outputs a value that isn't a character
+pub const NOT_A_CHARACTER: i32 = 2;
+
+/// A WebAssembly text module that runs the program `cfg` was made from when its exported
+/// `run` function is called, doing I/O through the functions it imports from `io`.
+///
+/// States are dispatched with a `br_table` into a block each, every one of which runs the
+/// command leaving its state, sets the next and goes round again. Commands run the way the
+/// spec says, so a program disassembled as any other dialect is refused.
+pub fn to_wat(cfg: &Cfg) -> Result<String, String> {
+    if *cfg.dialect() != Dialect::SPEC {
+        return Err("only programs run the way the spec says can be compiled".to_string());
+    }
+    let mut out = String::new();
+    writeln!(out, ";; Generated by cornelis").unwrap();
+    writeln!(out, "(module").unwrap();
+    out.push_str(RUNTIME);
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "  (func (export \"run\") (result i32) (local $state i32) (local $restricted i32) (local $t i32)"
+    )
+    .unwrap();
+    if cfg.states.is_empty() {
+        writeln!(out, "    i32.const {})", HALTED).unwrap();
+        writeln!(out, ")").unwrap();
+        return Ok(out);
+    }
+    writeln!(out, "    loop $next").unwrap();
+    writeln!(out, "    block $halt").unwrap();
+    for id in (0..cfg.states.len()).rev() {
+        writeln!(out, "    block $s{}", id).unwrap();
+    }
+    let labels: Vec<String> = (0..cfg.states.len())
+        .map(|id| format!("$s{}", id))
+        .collect();
+    writeln!(out, "    local.get $state").unwrap();
+    writeln!(out, "    br_table {} $halt", labels.join(" ")).unwrap();
+
+    for (id, edges) in cfg.edges_from().iter().enumerate() {
+        let state = cfg.states[id];
+        let block = cfg.block(id);
+        writeln!(out, "    end").unwrap();
+        writeln!(
+            out,
+            "    ;; block {} at ({}, {}) dp {} cc {}",
+            state.block,
+            block.codel.x,
+            block.codel.y,
+            direction(state.dp),
+            chooser(state.cc)
+        )
+        .unwrap();
+        let mut code = vec![];
+        let stop_unless = |code: &mut Vec<String>, call: &str, status: i32| {
+            code.push(format!("call {}", call));
+            code.push("i32.eqz".to_string());
+            code.push(format!("if\n  i32.const {}\n  return\nend", status));
+        };
+        match edges[..] {
+            // trapped in white
+            [] => code.push(format!("i32.const {}\nreturn", HALTED)),
+            [edge, _] if edge.command.is_none() => {
+                code.push(
+                    "local.get $restricted\ni32.const 1\ni32.add\nlocal.tee $restricted"
+                        .to_string(),
+                );
+                code.push(format!(
+                    "i32.const 8\ni32.ge_u\nif\n  i32.const {}\n  return\nend",
+                    HALTED
+                ));
+                code.push(format!(
+                    "i32.const {}\ni32.const {}\nlocal.get $restricted\ni32.const 1\ni32.and\nselect\nlocal.set $state",
+                    edges[0].to, edges[1].to
+                ));
+            }
+            [edge, ..] => {
+                code.push("i32.const 0\nlocal.set $restricted".to_string());
+                match edge.command.unwrap() {
+                    PietOp::Pointer | PietOp::Switch => {
+                        code.push(format!(
+                            "i64.const {}\ncall $branch\nlocal.set $t",
+                            edges.len()
+                        ));
+                        for (n, edge) in edges.iter().enumerate() {
+                            code.push(format!(
+                                "local.get $t\ni32.const {}\ni32.eq\nif\n  i32.const {}\n  local.set $state\nend",
+                                n, edge.to
+                            ));
+                        }
+                    }
+                    op => {
+                        match op {
+                            PietOp::None => {}
+                            PietOp::Push => {
+                                code.push(format!("i64.const {}\ncall $push", block.size))
+                            }
+                            PietOp::Pop => code.push("call $drop".to_string()),
+                            PietOp::Add => code.push("call $add".to_string()),
+                            PietOp::Subtract => code.push("call $sub".to_string()),
+                            PietOp::Multiply => code.push("call $mul".to_string()),
+                            PietOp::Divide => code.push("call $div".to_string()),
+                            PietOp::Mod => code.push("call $mod".to_string()),
+                            PietOp::Not => code.push("call $not".to_string()),
+                            PietOp::Greater => code.push("call $gt".to_string()),
+                            PietOp::Duplicate => code.push("call $dup".to_string()),
+                            PietOp::Roll => code.push("call $roll".to_string()),
+                            PietOp::OutNumber => code.push("call $outnum".to_string()),
+                            PietOp::OutChar => stop_unless(&mut code, "$outchar", NOT_A_CHARACTER),
+                            PietOp::InChar => stop_unless(&mut code, "$inchar", OUT_OF_INPUT),
+                            PietOp::InNumber => stop_unless(&mut code, "$innum", OUT_OF_INPUT),
+                            PietOp::Pointer | PietOp::Switch => unreachable!(),
+                        }
+                        code.push(format!("i32.const {}\nlocal.set $state", edge.to));
+                    }
+                }
+            }
+        }
+        code.push("br $next".to_string());
+        for line in code.iter().flat_map(|code| code.lines()) {
+            writeln!(out, "    {}", line).unwrap();
+        }
+    }
+    writeln!(out, "    end").unwrap();
+    writeln!(out, "    end").unwrap();
+    writeln!(out, "    i32.const {})", HALTED).unwrap();
+    writeln!(out, ")").unwrap();
+    Ok(out)
+}